@@ -1,12 +1,224 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 const SHADER_LOCATION: &str = "src/shader.comp";
+const SPIRV_LOCATION: &str = "./compute.spv";
+const SHADER_DIRECTORY: &str = "src/shaders";
 
-fn main() {
-	Command::new("glslangValidator")
-		.args(&["-o", "./compute.spv", "-V100", SHADER_LOCATION])
-		.status()
-		.unwrap();
+const VALIDATOR_ENV: &str = "GLSLANG_VALIDATOR";
+const OPTIMIZE_ENV: &str = "SPV_OPTIMIZE";
+const VALIDATOR_DEFAULT: &str = "glslangValidator";
+#[cfg(not(feature = "shaderc"))]
+const INSTALL_HINT: &str = "install glslangValidator from the Vulkan SDK \
+	(https://vulkan.lunarg.com/sdk/home) or your package manager (e.g. glslang-tools), \
+	set GLSLANG_VALIDATOR to its path, or build with --features shaderc";
 
+fn main() {
 	println!("cargo:rerun-if-changed={}", SHADER_LOCATION);
+	rerun_if_includes_change(Path::new(SHADER_LOCATION));
+	// a missing path would make cargo rerun this script on every build
+	if Path::new(SHADER_DIRECTORY).exists() {
+		println!("cargo:rerun-if-changed={}", SHADER_DIRECTORY);
+	}
+	println!("cargo:rerun-if-env-changed={}", VALIDATOR_ENV);
+	println!("cargo:rerun-if-env-changed={}", OPTIMIZE_ENV);
+
+	let validator = env::var(VALIDATOR_ENV).unwrap_or_else(|_| VALIDATOR_DEFAULT.to_string());
+
+	compile(
+		&validator,
+		Path::new(SHADER_LOCATION),
+		Path::new(SPIRV_LOCATION),
+	);
+
+	let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+	let mut entries = Vec::new();
+	for source in find_shaders(Path::new(SHADER_DIRECTORY)) {
+		println!("cargo:rerun-if-changed={}", source.display());
+		rerun_if_includes_change(&source);
+
+		let name = source.file_stem().unwrap().to_string_lossy().into_owned();
+		let spirv = out_dir.join(format!("{}.spv", name));
+		compile(&validator, &source, &spirv);
+		entries.push((name, spirv));
+	}
+
+	write_shader_table(&out_dir.join("shaders.rs"), &entries);
+}
+
+fn find_shaders(directory: &Path) -> Vec<PathBuf> {
+	let mut shaders = match fs::read_dir(directory) {
+		Ok(entries) => entries
+			.filter_map(|entry| entry.ok().map(|e| e.path()))
+			.filter(|path| path.extension().is_some_and(|ext| ext == "comp"))
+			.collect::<Vec<_>>(),
+		Err(_) => Vec::new(),
+	};
+	shaders.sort();
+	shaders
+}
+
+fn rerun_if_includes_change(source: &Path) {
+	let mut seen = BTreeSet::new();
+	collect_includes(source, &mut seen);
+	for include in seen {
+		println!("cargo:rerun-if-changed={}", include.display());
+	}
+}
+
+/// Files `source` pulls in through `#include`, directly or through other includes,
+/// resolved against the including file's directory as glslang does. Ones that don't
+/// exist are left for the compiler to complain about; watching a missing path would
+/// make cargo rerun this script on every build.
+fn collect_includes(source: &Path, seen: &mut BTreeSet<PathBuf>) {
+	let Ok(text) = fs::read_to_string(source) else {
+		return;
+	};
+	let directory = source.parent().unwrap_or(Path::new("."));
+	for line in text.lines() {
+		let Some(rest) = line.trim_start().strip_prefix('#') else {
+			continue;
+		};
+		let Some(rest) = rest.trim_start().strip_prefix("include") else {
+			continue;
+		};
+		let rest = rest.trim();
+		let name = match (rest.chars().next(), rest.get(1..)) {
+			(Some('"'), Some(tail)) => tail.split('"').next(),
+			(Some('<'), Some(tail)) => tail.split('>').next(),
+			_ => None,
+		};
+		let Some(name) = name.filter(|name| !name.is_empty()) else {
+			continue;
+		};
+		// canonical, so a cycle through `..` still comes back to a path already seen
+		let Ok(include) = directory.join(name).canonicalize() else {
+			continue;
+		};
+		if include.is_file() && seen.insert(include.clone()) {
+			collect_includes(&include, seen);
+		}
+	}
+}
+
+/// With SPV_OPTIMIZE set, release builds get size-optimized SPIR-V (glslangValidator
+/// has no plain `-O`) and debug builds get debug info; unset keeps the bare `-V100`.
+fn optimization_flags() -> &'static [&'static str] {
+	match env::var(OPTIMIZE_ENV) {
+		Ok(value) if !matches!(value.as_str(), "" | "0" | "false") => {
+			if env::var("PROFILE").as_deref() == Ok("release") {
+				&["-Os"]
+			} else {
+				&["-g"]
+			}
+		}
+		_ => &[],
+	}
+}
+
+fn compile(validator: &str, source: &Path, spirv: &Path) {
+	let output = match Command::new(validator)
+		.arg("-o")
+		.arg(spirv)
+		.arg("-V100")
+		.args(optimization_flags())
+		.arg(source)
+		.output()
+	{
+		Ok(output) => output,
+		#[cfg(feature = "shaderc")]
+		Err(_) => return compile_with_shaderc(source, spirv),
+		#[cfg(not(feature = "shaderc"))]
+		Err(e) if spirv.exists() => {
+			println!(
+				"cargo:warning=couldn't run {} ({}), reusing existing {}",
+				validator,
+				e,
+				spirv.display()
+			);
+			println!("cargo:warning={}", INSTALL_HINT);
+			return;
+		}
+		#[cfg(not(feature = "shaderc"))]
+		Err(e) => panic!("couldn't run {} ({})\n{}", validator, e, INSTALL_HINT),
+	};
+
+	if !output.status.success() {
+		// glslangValidator reports compile errors on stdout, not stderr
+		panic!(
+			"{} failed to compile {} ({})\n{}{}",
+			validator,
+			source.display(),
+			output.status,
+			String::from_utf8_lossy(&output.stdout),
+			String::from_utf8_lossy(&output.stderr)
+		);
+	}
+
+	// a zero exit doesn't promise a module, and include_bytes! on a missing or empty
+	// one fails much further away from the cause
+	match fs::metadata(spirv) {
+		Ok(metadata) if metadata.len() > 0 => {}
+		result => panic!(
+			"{} exited successfully but left {} {} for {}\n{}{}",
+			validator,
+			if result.is_ok() { "an empty" } else { "no" },
+			spirv.display(),
+			source.display(),
+			String::from_utf8_lossy(&output.stdout),
+			String::from_utf8_lossy(&output.stderr)
+		),
+	}
+}
+
+/// The same compile in-process, for when the validator isn't installed.
+#[cfg(feature = "shaderc")]
+fn compile_with_shaderc(source: &Path, spirv: &Path) {
+	let text = fs::read_to_string(source)
+		.unwrap_or_else(|e| panic!("couldn't read {} ({})", source.display(), e));
+
+	let compiler = shaderc::Compiler::new().expect("shaderc couldn't create a compiler");
+	let mut options = shaderc::CompileOptions::new().expect("shaderc couldn't create options");
+	for flag in optimization_flags() {
+		match *flag {
+			"-Os" => options.set_optimization_level(shaderc::OptimizationLevel::Size),
+			"-g" => options.set_generate_debug_info(),
+			_ => unreachable!("no shaderc equivalent for {}", flag),
+		}
+	}
+
+	let artifact = compiler
+		.compile_into_spirv(
+			&text,
+			shaderc::ShaderKind::Compute,
+			&source.display().to_string(),
+			"main",
+			Some(&options),
+		)
+		.unwrap_or_else(|e| panic!("shaderc failed to compile {}\n{}", source.display(), e));
+	if artifact.get_num_warnings() > 0 {
+		for line in artifact.get_warning_messages().lines() {
+			println!("cargo:warning={}", line);
+		}
+	}
+
+	fs::write(spirv, artifact.as_binary_u8())
+		.unwrap_or_else(|e| panic!("couldn't write {} ({})", spirv.display(), e));
+}
+
+fn write_shader_table(path: &Path, entries: &[(String, PathBuf)]) {
+	let mut table = String::from("pub const SHADERS: &[(&str, &[u8])] = &[\n");
+	for (name, spirv) in entries {
+		table.push_str(&format!(
+			"\t({:?}, include_bytes!({:?}) as &[u8]),\n",
+			name,
+			spirv.display().to_string()
+		));
+	}
+	table.push_str("];\n");
+
+	fs::write(path, table).unwrap();
 }
@@ -0,0 +1,13 @@
+use anyhow::Result;
+use vk_compute::app::AppBuilder;
+use vk_compute::context::run_compute;
+use vk_compute::ComputeConfig;
+
+/// Doubles a few floats with the bundled shader, the whole of the one-shot API.
+fn main() -> Result<()> {
+	let input = [1.0, 2.5, -3.0, 42.0];
+	let builder = AppBuilder::new().element_count(input.len());
+	let results = unsafe { run_compute(builder, &ComputeConfig::default(), &input)? };
+	println!("{:?} doubled is {:?}", input, results);
+	Ok(())
+}
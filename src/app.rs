@@ -0,0 +1,3011 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::{c_void, CStr, CString};
+use std::mem::size_of;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, error, info, trace, warn};
+use owo_colors::{AnsiColors, OwoColorize, Stream};
+use vulkanalia::loader::{LibloadingLoader, LIBRARY};
+use vulkanalia::prelude::v1_1::*;
+use vulkanalia::vk::{ExtDebugUtilsExtension, KhrBufferDeviceAddressExtension};
+
+use crate::info::{get_portability_subset_info, DeviceLimits};
+use crate::progress::Progress;
+use crate::{
+	align_up, checked_size, create_shader_module, device_error, get_compute_queue_family_index,
+	get_first_memory_type_index, get_heap_sizes, get_memory_type_index_for_requirements,
+	get_shader_clock_features, get_transfer_queue_family_index, has_portability_subset_extension,
+	load_shader, pick_physical_device, verify_requested_features, verify_subgroup_size,
+	ApplicationConfig, BindingConfig, BufferKind, BufferSharing, BufferUsage, ComputeConfig,
+	ComputeError, DevicePreference, ElementType, FeatureConfig, HeapSizes, PassConfig,
+	QueueSelection, StageTimings, VK_KHR_PORTABILITY_SUBSET,
+};
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+const VALIDATION_LAYER: vk::ExtensionName =
+	vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
+
+/// Set to `on` or `off` to load the validation layer regardless of the build profile.
+pub const VALIDATION_ENV: &str = "VK_COMPUTE_VALIDATION";
+
+pub const DEFAULT_ELEMENT_COUNT: usize = 16384;
+const NUM_BUFFERS: usize = 2;
+const FIRST_EXTRA_BINDING: u32 = NUM_BUFFERS as u32;
+
+pub unsafe fn create_entry() -> Result<Entry> {
+	let loader = LibloadingLoader::new(LIBRARY).map_err(|e| {
+		// dlopen's message already starts with the library's name
+		let reason = e.to_string();
+		ComputeError::NoLoader {
+			library: LIBRARY,
+			reason: reason
+				.strip_prefix(&format!("{}: ", LIBRARY))
+				.unwrap_or(&reason)
+				.to_owned(),
+		}
+	})?;
+	Entry::new(loader).map_err(|b| anyhow!("{}", b))
+}
+
+/// `validation_features` are switched on and `disabled_validation_features` off through
+/// `VK_EXT_validation_features`, and only when validation itself is on and the layer
+/// offers the extension. With validation, `VK_EXT_debug_utils` is enabled too where
+/// available, for [`create_debug_messenger`].
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub unsafe fn create_instance(
+	entry: &Entry,
+	application: &ApplicationConfig,
+	validation: bool,
+	validation_features: &[vk::ValidationFeatureEnableEXT],
+	disabled_validation_features: &[vk::ValidationFeatureDisableEXT],
+) -> Result<Instance> {
+	let application_name = CString::new(application.name.as_str())
+		.map_err(|_| anyhow!("application name can't contain a nul byte"))?;
+	let [major, minor, patch] = application.version;
+	let application_info = vk::ApplicationInfo::builder()
+		.application_name(application_name.as_bytes_with_nul())
+		.application_version(vk::make_version(major, minor, patch))
+		.engine_name(b"No Engine\0")
+		.engine_version(vk::make_version(1, 0, 0))
+		.api_version(vk::make_version(1, 1, 0))
+		.build();
+
+	let available_layers = entry
+		.enumerate_instance_layer_properties()?
+		.iter()
+		.map(|l| l.layer_name)
+		.collect::<HashSet<_>>();
+
+	if validation && !available_layers.contains(&VALIDATION_LAYER) {
+		return Err(anyhow!("Validation layer requested but not supported."));
+	}
+
+	log_validation(validation);
+
+	let layers = if validation {
+		vec![VALIDATION_LAYER.as_ptr()]
+	} else {
+		Vec::new()
+	};
+
+	let any_validation_features =
+		!validation_features.is_empty() || !disabled_validation_features.is_empty();
+	let use_validation_features =
+		validation && any_validation_features && has_validation_features_extension(entry)?;
+	if any_validation_features && !use_validation_features {
+		warn!("validation features requested but unavailable, ignoring them");
+	}
+
+	let mut extensions = if use_validation_features {
+		vec![vk::EXT_VALIDATION_FEATURES_EXTENSION.name]
+	} else {
+		Vec::new()
+	};
+	let available_extensions = entry.enumerate_instance_extension_properties(None)?;
+	if validation
+		&& available_extensions
+			.iter()
+			.any(|p| p.extension_name == vk::EXT_DEBUG_UTILS_EXTENSION.name)
+	{
+		extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name);
+	}
+	add_requested_extensions(
+		&mut extensions,
+		&application.instance_extensions,
+		&available_extensions,
+		"instance",
+	);
+	let extension_names = extensions.iter().map(|e| e.as_ptr()).collect::<Vec<_>>();
+
+	let mut features = vk::ValidationFeaturesEXT::builder()
+		.enabled_validation_features(validation_features)
+		.disabled_validation_features(disabled_validation_features)
+		.build();
+
+	let mut instance_create_info = vk::InstanceCreateInfo::builder()
+		.application_info(&application_info)
+		.enabled_layer_names(&layers)
+		.enabled_extension_names(&extension_names);
+	if use_validation_features {
+		instance_create_info = instance_create_info.push_next(&mut features);
+	}
+	Ok(entry
+		.create_instance(&instance_create_info.build(), None)
+		.map_err(ComputeError::Vulkan)?)
+}
+
+/// A messenger passing the validation layer's messages on to the log, errors as errors
+/// and warnings (best-practices advice among them) as warnings, or `None` when the
+/// instance doesn't have `VK_EXT_debug_utils` and the layer prints them itself.
+pub unsafe fn create_debug_messenger(
+	instance: &Instance,
+) -> Result<Option<vk::DebugUtilsMessengerEXT>> {
+	if !instance
+		.extensions()
+		.contains(&vk::EXT_DEBUG_UTILS_EXTENSION.name)
+	{
+		return Ok(None);
+	}
+	let info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+		.message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
+		.message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
+		.user_callback(Some(log_debug_message));
+	Ok(Some(
+		instance
+			.create_debug_utils_messenger_ext(&info, None)
+			.map_err(ComputeError::Vulkan)?,
+	))
+}
+
+extern "system" fn log_debug_message(
+	severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+	message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+	data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+	_: *mut c_void,
+) -> vk::Bool32 {
+	// the layer hands over a valid message for as long as the call lasts
+	let message = unsafe { CStr::from_ptr((*data).message) }.to_string_lossy();
+	let kind = if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+		"performance"
+	} else if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+		"validation"
+	} else {
+		"general"
+	};
+	if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+		error!("({}) {}", kind, message);
+	} else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+		warn!("({}) {}", kind, message);
+	} else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+		debug!("({}) {}", kind, message);
+	} else {
+		trace!("({}) {}", kind, message);
+	}
+	vk::FALSE
+}
+
+/// Appends the `requested` extensions that are `available` and not in `extensions` yet,
+/// warning about any that aren't offered.
+fn add_requested_extensions(
+	extensions: &mut Vec<vk::ExtensionName>,
+	requested: &[String],
+	available: &[vk::ExtensionProperties],
+	kind: &str,
+) {
+	for name in requested {
+		match available
+			.iter()
+			.find(|p| p.extension_name.to_string_lossy() == name.as_str())
+		{
+			Some(properties) if !extensions.contains(&properties.extension_name) => {
+				extensions.push(properties.extension_name);
+			}
+			Some(_) => {}
+			None => warn!(
+				"{} extension {} isn't supported, leaving it out",
+				kind, name
+			),
+		}
+	}
+}
+
+/// Whether to validate when nothing more specific says: `VK_COMPUTE_VALIDATION` if it's
+/// set, otherwise on in debug builds only.
+pub fn default_validation() -> bool {
+	match std::env::var(VALIDATION_ENV).as_deref() {
+		Ok("on" | "1" | "true") => true,
+		Ok("off" | "0" | "false") => false,
+		Ok(other) => {
+			warn!(
+				"ignoring {}={:?}, expected on or off",
+				VALIDATION_ENV, other
+			);
+			cfg!(debug_assertions)
+		}
+		Err(_) => cfg!(debug_assertions),
+	}
+}
+
+/// The extension comes from the validation layer rather than the loader.
+unsafe fn has_validation_features_extension(entry: &Entry) -> Result<bool> {
+	let extension_properties =
+		entry.enumerate_instance_extension_properties(Some(VALIDATION_LAYER.as_bytes()))?;
+	Ok(extension_properties
+		.iter()
+		.any(|p| p.extension_name == vk::EXT_VALIDATION_FEATURES_EXTENSION.name))
+}
+
+/// Everything [`App`] needs to know before touching Vulkan. The defaults run the bundled
+/// doubling shader over [`DEFAULT_ELEMENT_COUNT`] floats on the first compute device.
+#[derive(Clone, Debug)]
+pub struct AppBuilder {
+	shader_bytes: Vec<u8>,
+	element_count: usize,
+	entry_point: String,
+	validation: bool,
+	validation_features: Vec<vk::ValidationFeatureEnableEXT>,
+	disabled_validation_features: Vec<vk::ValidationFeatureDisableEXT>,
+	device_preference: DevicePreference,
+	queue_selection: QueueSelection,
+	prefer_timestamps: bool,
+	separate_transfer_queue: bool,
+	buffer_sharing: BufferSharing,
+	queue_priority: f32,
+	features: FeatureConfig,
+	application: ApplicationConfig,
+	element_type: ElementType,
+	element_stride: u32,
+	element_offset: u32,
+	dimensions: Option<[u32; 3]>,
+	local_size: [u32; 3],
+	subgroup_size: Option<u32>,
+	host_cached: bool,
+	memory_flags: Option<vk::MemoryPropertyFlags>,
+	buffer_usage: vk::BufferUsageFlags,
+}
+
+impl Default for AppBuilder {
+	fn default() -> Self {
+		Self {
+			shader_bytes: std::include_bytes!("../compute.spv").to_vec(),
+			element_count: DEFAULT_ELEMENT_COUNT,
+			entry_point: String::from("main"),
+			validation: default_validation(),
+			validation_features: Vec::new(),
+			disabled_validation_features: Vec::new(),
+			device_preference: DevicePreference::First,
+			queue_selection: QueueSelection::default(),
+			prefer_timestamps: false,
+			separate_transfer_queue: false,
+			buffer_sharing: BufferSharing::default(),
+			queue_priority: 1.0,
+			features: FeatureConfig::default(),
+			application: ApplicationConfig::default(),
+			element_type: ElementType::default(),
+			element_stride: 1,
+			element_offset: 0,
+			dimensions: None,
+			local_size: [1, 1, 1],
+			subgroup_size: None,
+			host_cached: false,
+			memory_flags: None,
+			buffer_usage: vk::BufferUsageFlags::empty(),
+		}
+	}
+}
+
+impl AppBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// SPIR-V for the shader run when no passes are configured.
+	pub fn shader_bytes(mut self, bytes: impl Into<Vec<u8>>) -> Self {
+		self.shader_bytes = bytes.into();
+		self
+	}
+
+	pub fn shader_code(&self) -> &[u8] {
+		&self.shader_bytes
+	}
+
+	/// How many floats go in each of the input and output buffers, one invocation each.
+	pub fn element_count(mut self, count: usize) -> Self {
+		self.element_count = count;
+		self
+	}
+
+	/// How many elements the buffers will hold, after [`AppBuilder::dimensions`].
+	pub fn get_element_count(&self) -> usize {
+		self.element_count
+	}
+
+	pub fn get_dimensions(&self) -> Option<[u32; 3]> {
+		self.dimensions
+	}
+
+	/// `F16` and `F64` turn on the device features their buffers need.
+	pub fn element_type(mut self, element_type: ElementType) -> Self {
+		self.element_type = element_type;
+		self
+	}
+
+	/// Puts each element `stride` positions after the last in the input and output
+	/// buffers, at `offset` within its stride, for shaders reading one component of
+	/// interleaved data. The positions in between are uploaded as zeros and skipped on
+	/// readback, and the buffers grow to hold them all.
+	pub fn element_stride(mut self, stride: u32, offset: u32) -> Self {
+		self.element_stride = stride;
+		self.element_offset = offset;
+		self
+	}
+
+	/// How many positions the input and output buffers each hold, every element and the
+	/// gaps [`AppBuilder::element_stride`] leaves around it.
+	fn buffer_positions(&self) -> Result<usize> {
+		if self.element_stride == 0 {
+			return Err(anyhow!("element_stride has to be at least 1"));
+		}
+		if self.element_offset >= self.element_stride {
+			return Err(anyhow!(
+				"element_offset {} is outside an element_stride of {}",
+				self.element_offset,
+				self.element_stride
+			));
+		}
+		self.element_count
+			.checked_mul(self.element_stride as usize)
+			.ok_or_else(|| {
+				anyhow!(
+					"{} elements {} apart are more than a buffer can hold",
+					self.element_count,
+					self.element_stride
+				)
+			})
+	}
+
+	/// Lays the elements out as a width x height x depth grid, and sets `element_count`
+	/// to match.
+	pub fn dimensions(mut self, dimensions: [u32; 3]) -> Self {
+		self.element_count = dimensions.iter().map(|d| *d as usize).product();
+		self.dimensions = Some(dimensions);
+		self
+	}
+
+	/// The shaders' `local_size_x/y/z`, which the group counts get divided by.
+	pub fn local_size(mut self, local_size: [u32; 3]) -> Self {
+		self.local_size = local_size;
+		self
+	}
+
+	/// Requires every pipeline to run with subgroups this wide, which the device has to
+	/// support through `VK_EXT_subgroup_size_control`.
+	pub fn subgroup_size(mut self, size: u32) -> Self {
+		self.subgroup_size = Some(size);
+		self
+	}
+
+	/// Asks for `HOST_CACHED` memory for the input and output buffers, which the host
+	/// reads back much faster, falling back to the usual host-coherent memory.
+	pub fn host_cached(mut self, host_cached: bool) -> Self {
+		self.host_cached = host_cached;
+		self
+	}
+
+	/// Asks for a memory type with `flags` for the input and output buffers, which have
+	/// to include `HOST_VISIBLE`, falling back to host-coherent memory. Takes precedence
+	/// over [`AppBuilder::host_cached`].
+	pub fn memory_flags(mut self, flags: vk::MemoryPropertyFlags) -> Self {
+		self.memory_flags = Some(flags);
+		self
+	}
+
+	/// Creates the input and output buffers with `usage` as well as what they always
+	/// have, `STORAGE_BUFFER` and `TRANSFER_DST`.
+	pub fn buffer_usage(mut self, usage: vk::BufferUsageFlags) -> Self {
+		self.buffer_usage = usage;
+		self
+	}
+
+	/// The memory properties to try for the input and output buffers, best first.
+	fn memory_candidates(&self) -> Vec<vk::MemoryPropertyFlags> {
+		let visible = vk::MemoryPropertyFlags::HOST_VISIBLE;
+		let coherent = visible | vk::MemoryPropertyFlags::HOST_COHERENT;
+		let cached = visible | vk::MemoryPropertyFlags::HOST_CACHED;
+		match self.memory_flags {
+			Some(flags) => vec![flags, coherent],
+			None if self.host_cached => vec![cached | coherent, cached, coherent],
+			None => vec![coherent],
+		}
+	}
+
+	/// The function every pipeline starts in, `main` unless told otherwise.
+	pub fn entry_point(mut self, name: impl Into<String>) -> Self {
+		self.entry_point = name.into();
+		self
+	}
+
+	/// Whether to load the Khronos validation layer; see [`default_validation`].
+	pub fn validation(mut self, enabled: bool) -> Self {
+		self.validation = enabled;
+		self
+	}
+
+	pub fn validation_features(mut self, features: &[vk::ValidationFeatureEnableEXT]) -> Self {
+		self.validation_features = features.to_vec();
+		self
+	}
+
+	/// Parts of validation to leave out, e.g. all but best practices.
+	pub fn disabled_validation_features(
+		mut self,
+		features: &[vk::ValidationFeatureDisableEXT],
+	) -> Self {
+		self.disabled_validation_features = features.to_vec();
+		self
+	}
+
+	pub fn device_preference(mut self, preference: DevicePreference) -> Self {
+		self.device_preference = preference;
+		self
+	}
+
+	pub fn queue_selection(mut self, selection: QueueSelection) -> Self {
+		self.queue_selection = selection;
+		self
+	}
+
+	/// Steer queue selection towards a family with `timestampValidBits`, for GPU timing.
+	pub fn prefer_timestamps(mut self, prefer: bool) -> Self {
+		self.prefer_timestamps = prefer;
+		self
+	}
+
+	pub fn separate_transfer_queue(mut self, separate: bool) -> Self {
+		self.separate_transfer_queue = separate;
+		self
+	}
+
+	/// Only matters with a separate transfer queue; see [`BufferSharing`].
+	pub fn buffer_sharing(mut self, sharing: BufferSharing) -> Self {
+		self.buffer_sharing = sharing;
+		self
+	}
+
+	/// Clamped to 0.0..=1.0, the range Vulkan allows.
+	pub fn queue_priority(mut self, priority: f32) -> Self {
+		let clamped = priority.clamp(0.0, 1.0);
+		if clamped != priority {
+			warn!(
+				"queue_priority {} is outside 0.0..=1.0, using {}",
+				priority, clamped
+			);
+		}
+		self.queue_priority = clamped;
+		self
+	}
+
+	pub fn features(mut self, features: FeatureConfig) -> Self {
+		self.features = features;
+		self
+	}
+
+	/// The name drivers and tools like RenderDoc show for this instance.
+	pub fn application_name(mut self, name: impl Into<String>) -> Self {
+		self.application.name = name.into();
+		self
+	}
+
+	pub fn application_version(mut self, major: u32, minor: u32, patch: u32) -> Self {
+		self.application.version = [major, minor, patch];
+		self
+	}
+
+	pub unsafe fn build(self) -> Result<App> {
+		App::create(self)
+	}
+}
+
+/// One buffer's worth of floats, padded so the next buffer's binding offset meets
+/// `minStorageBufferOffsetAlignment`.
+/// Also checks that [`NUM_BUFFERS`] strides fit in a `DeviceSize`, so they can be
+/// added up unchecked afterwards.
+fn buffer_stride(
+	element_count: usize,
+	element_type: ElementType,
+	limits: &DeviceLimits,
+) -> Result<vk::DeviceSize> {
+	let size = checked_size(element_count, element_type.size())?;
+	align_up(size, limits.min_storage_buffer_offset_alignment)
+		.filter(|stride| stride.checked_mul(NUM_BUFFERS as vk::DeviceSize).is_some())
+		.ok_or_else(|| {
+			anyhow!(
+				"{} buffers of {} elements are more than memory can hold",
+				NUM_BUFFERS,
+				element_count
+			)
+		})
+}
+
+/// Enough workgroups of `local_size` to cover `dimensions`, checked against what the
+/// device can dispatch along each axis. The local size is checked first, axis by axis
+/// and then as a whole, since the driver only rejects the pipeline vaguely.
+fn group_counts(
+	dimensions: [u32; 3],
+	local_size: [u32; 3],
+	limits: &DeviceLimits,
+) -> Result<[u32; 3]> {
+	const AXES: [&str; 3] = ["x", "y", "z"];
+	for (axis, name) in AXES.iter().enumerate() {
+		let size = local_size[axis];
+		if size == 0 || size > limits.max_compute_work_group_size[axis] {
+			return Err(anyhow!(
+				"local size {} along {} must be between 1 and the device's maxComputeWorkGroupSize of {}",
+				size,
+				name,
+				limits.max_compute_work_group_size[axis]
+			));
+		}
+	}
+	let invocations = local_size.iter().map(|s| *s as u64).product::<u64>();
+	if invocations > limits.max_compute_work_group_invocations as u64 {
+		return Err(anyhow!(
+			"local size {:?} is {} invocations, over the device's maxComputeWorkGroupInvocations of {}",
+			local_size,
+			invocations,
+			limits.max_compute_work_group_invocations
+		));
+	}
+
+	let mut counts = [0; 3];
+	for (axis, name) in AXES.iter().enumerate() {
+		counts[axis] = dimensions[axis].div_ceil(local_size[axis]);
+		if counts[axis] > limits.max_compute_work_group_count[axis] {
+			return Err(anyhow!(
+				"{} workgroups along {} is over the device's limit of {}",
+				counts[axis],
+				name,
+				limits.max_compute_work_group_count[axis]
+			));
+		}
+	}
+	Ok(counts)
+}
+
+/// A configured binding beyond the input and output buffers, with its own memory or
+/// a window onto another binding's buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct ExtraBuffer {
+	pub set: u32,
+	pub binding: u32,
+	pub kind: BufferKind,
+	pub buffer: vk::Buffer,
+	/// `None` for views, whose buffer belongs to the binding they view
+	pub memory: Option<vk::DeviceMemory>,
+	/// the bytes the descriptor covers, `range` being `WHOLE_SIZE` for the rest
+	pub offset: vk::DeviceSize,
+	pub range: vk::DeviceSize,
+	/// f32s in the buffer, which is the viewed one for views
+	pub element_count: usize,
+	/// read back after each dispatch
+	pub readback: bool,
+}
+
+/// Where the input and output buffers' bytes are: one allocation with the output a
+/// stride in, or an allocation each when the driver prefers dedicated ones.
+#[derive(Clone, Copy, Debug)]
+pub enum BufferMemory {
+	Shared(vk::DeviceMemory),
+	Dedicated([vk::DeviceMemory; NUM_BUFFERS]),
+}
+
+impl BufferMemory {
+	/// The allocation and offset of the input (0) or output (1) buffer.
+	pub fn location(
+		&self,
+		index: usize,
+		stride: vk::DeviceSize,
+	) -> (vk::DeviceMemory, vk::DeviceSize) {
+		match self {
+			Self::Shared(memory) => (*memory, index as vk::DeviceSize * stride),
+			Self::Dedicated(memories) => (memories[index], 0),
+		}
+	}
+
+	pub fn allocations(&self) -> &[vk::DeviceMemory] {
+		match self {
+			Self::Shared(memory) => std::slice::from_ref(memory),
+			Self::Dedicated(memories) => memories,
+		}
+	}
+}
+
+/// Allocates `size` bytes of `memory_index`, addressable if `device_address`, and
+/// dedicated to `dedicated_buffer` if there is one.
+unsafe fn allocate_buffer_memory(
+	device: &Device,
+	memory_index: u32,
+	size: vk::DeviceSize,
+	device_address: bool,
+	dedicated_buffer: Option<vk::Buffer>,
+) -> Result<vk::DeviceMemory> {
+	// buffers can only hand out addresses from memory allocated for it
+	let mut allocate_flags = vk::MemoryAllocateFlagsInfo::builder()
+		.flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS)
+		.build();
+	let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder()
+		.buffer(dedicated_buffer.unwrap_or_default())
+		.build();
+	let mut memory_allocate_info = vk::MemoryAllocateInfo::builder()
+		.allocation_size(size)
+		.memory_type_index(memory_index);
+	if device_address {
+		memory_allocate_info = memory_allocate_info.push_next(&mut allocate_flags);
+	}
+	if dedicated_buffer.is_some() {
+		memory_allocate_info = memory_allocate_info.push_next(&mut dedicated_info);
+	}
+	Ok(device
+		.allocate_memory(&memory_allocate_info.build(), None)
+		.map_err(ComputeError::Vulkan)?)
+}
+
+/// Creates an input and an output buffer of `size` bytes and binds them to memory of
+/// `memory_index`: an allocation each if the driver prefers or requires dedicated ones
+/// for buffers like these, otherwise a single one with the output `stride` bytes in.
+/// With more than one of `queue_families` the buffers are shared concurrently between
+/// them, so no ownership transfers are needed. `heap` is what the memory type's heap
+/// has room for and its index, checked before anything's allocated.
+#[allow(clippy::too_many_arguments)]
+unsafe fn create_buffer_pair(
+	device: &Device,
+	memory_index: u32,
+	heap: (&HeapSizes, u32),
+	size: vk::DeviceSize,
+	stride: vk::DeviceSize,
+	device_address: bool,
+	extra_usage: vk::BufferUsageFlags,
+	queue_families: &[u32],
+) -> Result<(BufferMemory, vk::Buffer, vk::Buffer)> {
+	let mut usage =
+		vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST | extra_usage;
+	if device_address {
+		usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+	}
+	let mut buffer_info = vk::BufferCreateInfo::builder()
+		.size(size)
+		.usage(usage)
+		.sharing_mode(vk::SharingMode::EXCLUSIVE);
+	if queue_families.len() > 1 {
+		buffer_info = buffer_info
+			.sharing_mode(vk::SharingMode::CONCURRENT)
+			.queue_family_indices(queue_families);
+	}
+	let buffer_info = buffer_info.build();
+	let in_buffer = device.create_buffer(&buffer_info, None)?;
+	let out_buffer = match device.create_buffer(&buffer_info, None) {
+		Ok(buffer) => buffer,
+		Err(e) => {
+			device.destroy_buffer(in_buffer, None);
+			return Err(e.into());
+		}
+	};
+
+	match bind_buffer_pair(
+		device,
+		memory_index,
+		heap,
+		stride,
+		device_address,
+		usage,
+		in_buffer,
+		out_buffer,
+	) {
+		Ok(memory) => Ok((memory, in_buffer, out_buffer)),
+		Err(e) => {
+			device.destroy_buffer(in_buffer, None);
+			device.destroy_buffer(out_buffer, None);
+			Err(e)
+		}
+	}
+}
+
+/// Allocates and binds memory for [`create_buffer_pair`]'s buffers, freeing whatever it
+/// allocated again if it fails; the buffers are left to the caller.
+#[allow(clippy::too_many_arguments)]
+unsafe fn bind_buffer_pair(
+	device: &Device,
+	memory_index: u32,
+	heap: (&HeapSizes, u32),
+	stride: vk::DeviceSize,
+	device_address: bool,
+	usage: vk::BufferUsageFlags,
+	in_buffer: vk::Buffer,
+	out_buffer: vk::Buffer,
+) -> Result<BufferMemory> {
+	// both buffers are created alike, so one answers for the pair
+	let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+	let mut requirements = vk::MemoryRequirements2::builder()
+		.push_next(&mut dedicated_requirements)
+		.build();
+	let requirements_info = vk::BufferMemoryRequirementsInfo2::builder()
+		.buffer(in_buffer)
+		.build();
+	device.get_buffer_memory_requirements2(&requirements_info, &mut requirements);
+	let requirements = requirements.memory_requirements;
+	// usages can narrow down the memory types a buffer may live in
+	if requirements.memory_type_bits & (1 << memory_index) == 0 {
+		return Err(anyhow!(
+			"memory type {} can't hold buffers with usage {:?}",
+			memory_index,
+			usage
+		));
+	}
+
+	let dedicated = dedicated_requirements.prefers_dedicated_allocation == vk::TRUE
+		|| dedicated_requirements.requires_dedicated_allocation == vk::TRUE;
+	let (heap_sizes, heap_index) = heap;
+	let total = match dedicated {
+		true => NUM_BUFFERS as vk::DeviceSize * requirements.size,
+		false => NUM_BUFFERS as vk::DeviceSize * stride,
+	};
+	heap_sizes.check(heap_index, total)?;
+
+	if dedicated {
+		debug!("the driver prefers dedicated allocations for the input and output buffers");
+		let in_memory = allocate_buffer_memory(
+			device,
+			memory_index,
+			requirements.size,
+			device_address,
+			Some(in_buffer),
+		)?;
+		let out_memory = match allocate_buffer_memory(
+			device,
+			memory_index,
+			requirements.size,
+			device_address,
+			Some(out_buffer),
+		) {
+			Ok(memory) => memory,
+			Err(e) => {
+				device.free_memory(in_memory, None);
+				return Err(e);
+			}
+		};
+		let memory = BufferMemory::Dedicated([in_memory, out_memory]);
+		let bound = device
+			.bind_buffer_memory(in_buffer, in_memory, 0)
+			.and_then(|_| device.bind_buffer_memory(out_buffer, out_memory, 0));
+		return bound_or_freed(device, memory, bound);
+	}
+
+	if !stride.is_multiple_of(requirements.alignment) {
+		return Err(anyhow!(
+			"output buffer offset {} isn't a multiple of the required alignment {}",
+			stride,
+			requirements.alignment
+		));
+	}
+	let memory = allocate_buffer_memory(
+		device,
+		memory_index,
+		NUM_BUFFERS as vk::DeviceSize * stride,
+		device_address,
+		None,
+	)?;
+	let bound = device
+		.bind_buffer_memory(in_buffer, memory, 0)
+		.and_then(|_| device.bind_buffer_memory(out_buffer, memory, stride));
+	bound_or_freed(device, BufferMemory::Shared(memory), bound)
+}
+
+/// `memory` if binding it went through, otherwise the error with `memory` freed.
+unsafe fn bound_or_freed(
+	device: &Device,
+	memory: BufferMemory,
+	bound: VkResult<()>,
+) -> Result<BufferMemory> {
+	match bound {
+		Ok(()) => Ok(memory),
+		Err(e) => {
+			for allocation in memory.allocations() {
+				device.free_memory(*allocation, None);
+			}
+			Err(e.into())
+		}
+	}
+}
+
+/// One binding in a descriptor set layout, however it was worked out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+	pub set: u32,
+	pub binding: u32,
+	pub descriptor_type: vk::DescriptorType,
+	pub count: u32,
+}
+
+/// The input and output buffers at bindings 0 and 1 of `descriptor_set`, then
+/// `extra_bindings` from 2 on, in their own set or that one.
+pub fn config_descriptor_bindings(
+	extra_bindings: &[BindingConfig],
+	descriptor_set: u32,
+) -> Vec<DescriptorBinding> {
+	let storage = |binding| DescriptorBinding {
+		set: descriptor_set,
+		binding,
+		descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+		count: 1,
+	};
+	let mut bindings = vec![storage(0), storage(1)];
+	bindings.extend(
+		extra_bindings
+			.iter()
+			.enumerate()
+			.map(|(i, binding)| DescriptorBinding {
+				set: binding.set.unwrap_or(descriptor_set),
+				binding: FIRST_EXTRA_BINDING + i as u32,
+				descriptor_type: binding.kind.descriptor_type(),
+				count: 1,
+			}),
+	);
+	bindings
+}
+
+pub fn layout_bindings(bindings: &[DescriptorBinding]) -> Vec<vk::DescriptorSetLayoutBinding> {
+	bindings
+		.iter()
+		.map(|binding| {
+			vk::DescriptorSetLayoutBinding::builder()
+				.binding(binding.binding)
+				.descriptor_type(binding.descriptor_type)
+				.descriptor_count(binding.count)
+				.stage_flags(vk::ShaderStageFlags::COMPUTE)
+				.build()
+		})
+		.collect()
+}
+
+/// One past the highest set any of `bindings` is in, so every set below gets a layout,
+/// empty or not.
+pub fn set_count(bindings: &[DescriptorBinding]) -> u32 {
+	bindings.iter().map(|b| b.set + 1).max().unwrap_or(0)
+}
+
+/// Enough descriptors of each type for `set_count` sets laid out as `bindings`.
+pub fn pool_sizes(bindings: &[DescriptorBinding], set_count: u32) -> Vec<vk::DescriptorPoolSize> {
+	let mut sizes: Vec<vk::DescriptorPoolSize> = Vec::new();
+	for binding in bindings {
+		match sizes
+			.iter_mut()
+			.find(|size| size.type_ == binding.descriptor_type)
+		{
+			Some(size) => size.descriptor_count += binding.count * set_count,
+			None => sizes.push(vk::DescriptorPoolSize {
+				type_: binding.descriptor_type,
+				descriptor_count: binding.count * set_count,
+			}),
+		}
+	}
+	sizes
+}
+
+/// Descriptor pools that sets are allocated from and freed back to one at a time, so
+/// batch jobs don't need a pool each. When every pool is out of room another one twice
+/// the size of the last is added.
+#[derive(Clone, Debug, Default)]
+pub struct DescriptorSetPool {
+	bindings: Vec<DescriptorBinding>,
+	/// each with how many sets it holds, newest last
+	pools: Vec<(vk::DescriptorPool, u32)>,
+	/// sets allocated and not yet freed
+	in_use: u32,
+}
+
+impl DescriptorSetPool {
+	/// Pools for sets laid out as some of `bindings`. None are created until needed.
+	pub fn new(bindings: &[DescriptorBinding]) -> Self {
+		Self {
+			bindings: bindings.to_vec(),
+			..Self::default()
+		}
+	}
+
+	/// Makes sure there's room for `set_count` more sets, so a batch of jobs allocating
+	/// them one after another doesn't grow the pools partway.
+	pub unsafe fn reserve(&mut self, device: &Device, set_count: u32) -> Result<()> {
+		let capacity = self.pools.iter().map(|(_, max_sets)| max_sets).sum::<u32>();
+		let needed = self.in_use + set_count;
+		if needed > capacity {
+			self.add_pool(device, needed - capacity)?;
+		}
+		Ok(())
+	}
+
+	unsafe fn add_pool(&mut self, device: &Device, max_sets: u32) -> Result<vk::DescriptorPool> {
+		// a set of any layout fits in one copy of every binding, which is generous
+		let pool_sizes = pool_sizes(&self.bindings, max_sets);
+		let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+			.flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+			.max_sets(max_sets)
+			.pool_sizes(&pool_sizes)
+			.build();
+		let pool = device.create_descriptor_pool(&pool_create_info, None)?;
+		self.pools.push((pool, max_sets));
+		Ok(pool)
+	}
+
+	/// A set for each of `layouts`, all from one pool, which is handed back too for
+	/// [`DescriptorSetPool::free`].
+	pub unsafe fn allocate(
+		&mut self,
+		device: &Device,
+		layouts: &[vk::DescriptorSetLayout],
+	) -> Result<(vk::DescriptorPool, Vec<vk::DescriptorSet>)> {
+		let allocate = |pool| {
+			let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+				.descriptor_pool(pool)
+				.set_layouts(layouts)
+				.build();
+			device.allocate_descriptor_sets(&allocate_info)
+		};
+
+		let mut allocated = None;
+		for (pool, _) in self.pools.iter().rev() {
+			match allocate(*pool) {
+				Ok(sets) => {
+					allocated = Some((*pool, sets));
+					break;
+				}
+				Err(vk::ErrorCode::OUT_OF_POOL_MEMORY | vk::ErrorCode::FRAGMENTED_POOL) => {}
+				Err(e) => return Err(e.into()),
+			}
+		}
+		let (pool, sets) = match allocated {
+			Some(allocated) => allocated,
+			None => {
+				let last = self.pools.last().map_or(0, |(_, max_sets)| *max_sets);
+				let pool = self.add_pool(device, (last * 2).max(layouts.len() as u32))?;
+				(pool, allocate(pool)?)
+			}
+		};
+		self.in_use += sets.len() as u32;
+		Ok((pool, sets))
+	}
+
+	/// `sets` must have come from `pool` and no longer be in use by the device.
+	pub unsafe fn free(
+		&mut self,
+		device: &Device,
+		pool: vk::DescriptorPool,
+		sets: &[vk::DescriptorSet],
+	) {
+		// freeing only fails on misuse, and the sets are unusable either way
+		let _ = device.free_descriptor_sets(pool, sets);
+		self.in_use -= sets.len() as u32;
+	}
+
+	pub unsafe fn destroy(&mut self, device: &Device) {
+		for (pool, _) in self.pools.drain(..) {
+			device.destroy_descriptor_pool(pool, None);
+		}
+		self.in_use = 0;
+	}
+}
+
+/// `layouts` with the one of `descriptor_set` again at the end, for the set that has the
+/// input and output buffers the other way round.
+pub fn ping_pong_layouts(
+	layouts: &[vk::DescriptorSetLayout],
+	descriptor_set: u32,
+) -> Vec<vk::DescriptorSetLayout> {
+	let mut ping_pong = layouts.to_vec();
+	ping_pong.push(layouts[descriptor_set as usize]);
+	ping_pong
+}
+
+/// A host-visible copy of the input buffer and what it takes to copy it across on the
+/// transfer queue, signalling `semaphore` for the compute submit to wait on.
+#[derive(Clone, Copy, Debug)]
+pub struct StagedUpload {
+	pub buffer: vk::Buffer,
+	pub memory: vk::DeviceMemory,
+	pub command_pool: vk::CommandPool,
+	/// the copy into the input buffer, recorded once and submitted every dispatch
+	pub command_buffer: vk::CommandBuffer,
+	pub semaphore: vk::Semaphore,
+}
+
+/// One entry of a batch: its own input and output buffers in memory of their own,
+/// descriptor sets over them, a command buffer and a fence to wait on.
+#[derive(Clone, Debug)]
+pub struct BatchJob {
+	pub memory: BufferMemory,
+	pub in_buffer: vk::Buffer,
+	pub out_buffer: vk::Buffer,
+	/// the one of the [`DescriptorSetPool`]'s pools the sets came from
+	pub descriptor_pool: vk::DescriptorPool,
+	/// to bind for even and odd passes
+	pub descriptor_sets: [Vec<vk::DescriptorSet>; 2],
+	pub command_buffer: vk::CommandBuffer,
+	pub fence: vk::Fence,
+	pub buffer_addresses: Option<[vk::DeviceAddress; 2]>,
+}
+
+#[derive(Clone, Debug)]
+pub struct App {
+	pub entry: Entry,
+	pub instance: Instance,
+	pub physical_device: vk::PhysicalDevice,
+	pub logical_device: Device,
+	pub queue_index: u32,
+	/// of the compute family's timestamps, 0 if it can't write them
+	pub timestamp_valid_bits: u32,
+	pub transfer_queue_index: Option<u32>,
+	/// whether the input and output buffers are shared with the transfer family
+	pub buffer_sharing: BufferSharing,
+	pub memory_index: u32,
+	pub memory_heap_index: u32,
+	/// tells whether `memory` ended up device-local or in slower host memory
+	pub memory_flags: vk::MemoryPropertyFlags,
+	pub memory: BufferMemory,
+	pub compute_shader: vk::ShaderModule,
+	pub done_fence: vk::Fence,
+	pub element_count: usize,
+	pub element_type: ElementType,
+	/// positions from one element to the next in the input and output buffers, see
+	/// [`AppBuilder::element_stride`]
+	pub element_stride: u32,
+	pub element_offset: u32,
+	/// workgroups along x, y and z for every dispatch
+	pub group_counts: [u32; 3],
+	pub local_size: [u32; 3],
+	/// the subgroup size every pipeline requires, if pinned
+	pub subgroup_size: Option<u32>,
+	/// whether the input and output buffers have device addresses, pushed as constants
+	pub buffer_device_address: bool,
+	/// the input and output buffers' usages beyond storage and transfer destination
+	pub buffer_usage: vk::BufferUsageFlags,
+	/// a portability implementation like MoltenVK, whose features and limits carry
+	/// extra constraints; see [`crate::info::get_portability_subset_info`]
+	pub portability_subset: bool,
+	/// queried once here rather than wherever a limit matters
+	pub limits: DeviceLimits,
+	debug_messenger: Option<vk::DebugUtilsMessengerEXT>,
+	entry_point: CString,
+	in_buffer: vk::Buffer,
+	out_buffer: vk::Buffer,
+	buffer_size: vk::DeviceSize,
+	buffer_stride: vk::DeviceSize,
+	/// instance, device, shader module and buffers
+	pub setup_timings: StageTimings,
+}
+
+impl App {
+	unsafe fn create(builder: AppBuilder) -> Result<App> {
+		let entry_point = CString::new(builder.entry_point.as_str())
+			.map_err(|_| anyhow!("entry point name can't contain a nul byte"))?;
+		// before anything is created, rather than once the sizes have wrapped around
+		checked_size(
+			builder.buffer_positions()?,
+			NUM_BUFFERS * builder.element_type.size(),
+		)?;
+
+		let mut feature_config = builder.features.clone();
+		match builder.element_type {
+			ElementType::F32 => {}
+			ElementType::F16 => {
+				feature_config.shader_float16 = true;
+				feature_config.storage_buffer_16bit_access = true;
+			}
+			ElementType::F64 => feature_config.shader_float64 = true,
+		}
+
+		let mut setup_timings = StageTimings::start();
+		let entry = create_entry()?;
+		let instance = create_instance(
+			&entry,
+			&builder.application,
+			builder.validation,
+			&builder.validation_features,
+			&builder.disabled_validation_features,
+		)?;
+		let debug_messenger =
+			create_debug_messenger(&instance).inspect_err(|_| instance.destroy_instance(None))?;
+		setup_timings.end("instance");
+		Self::create_on_instance(
+			builder,
+			entry,
+			instance.clone(),
+			entry_point,
+			feature_config,
+			setup_timings,
+		)
+		.map(|app| App {
+			debug_messenger,
+			..app
+		})
+		.inspect_err(|_| {
+			if let Some(messenger) = debug_messenger {
+				instance.destroy_debug_utils_messenger_ext(messenger, None);
+			}
+			instance.destroy_instance(None);
+		})
+	}
+
+	/// Everything after the instance, which [`App::create`] destroys again if this fails.
+	unsafe fn create_on_instance(
+		builder: AppBuilder,
+		entry: Entry,
+		instance: Instance,
+		entry_point: CString,
+		feature_config: FeatureConfig,
+		mut setup_timings: StageTimings,
+	) -> Result<App> {
+		let feature_config = &feature_config;
+		let physical_device = pick_physical_device(&instance, builder.device_preference)?;
+		let limits = DeviceLimits::from(
+			&instance
+				.get_physical_device_properties(physical_device)
+				.limits,
+		);
+		info!(
+			"device allows {} bytes of shared memory per workgroup",
+			limits.max_compute_shared_memory_size
+		);
+
+		let dimensions = builder
+			.dimensions
+			.unwrap_or([builder.element_count as u32, 1, 1]);
+		let element_count = dimensions.iter().map(|d| *d as usize).product::<usize>();
+		if element_count != builder.element_count {
+			return Err(anyhow!(
+				"dimensions {:?} cover {} elements, not {}",
+				dimensions,
+				element_count,
+				builder.element_count
+			));
+		}
+		let group_counts = group_counts(dimensions, builder.local_size, &limits)?;
+		let buffer_positions = builder.buffer_positions()?;
+		let buffer_size = checked_size(buffer_positions, builder.element_type.size())?;
+		let buffer_stride = buffer_stride(buffer_positions, builder.element_type, &limits)?;
+
+		let (compute_queue_index, timestamp_valid_bits) = get_compute_queue_family_index(
+			&instance,
+			physical_device,
+			builder.queue_selection,
+			builder.prefer_timestamps,
+		)?;
+		let transfer_queue_index = if builder.separate_transfer_queue {
+			get_transfer_queue_family_index(&instance, physical_device)
+				.filter(|i| *i != compute_queue_index)
+		} else {
+			None
+		};
+		let queue_priorities = &[builder.queue_priority];
+		let queue_infos = std::iter::once(compute_queue_index)
+			.chain(transfer_queue_index)
+			.map(|family| {
+				vk::DeviceQueueCreateInfo::builder()
+					.queue_family_index(family)
+					.queue_priorities(queue_priorities)
+					.build()
+			})
+			.collect::<Vec<_>>();
+
+		let layers = if builder.validation {
+			vec![VALIDATION_LAYER.as_ptr()]
+		} else {
+			Vec::new()
+		};
+
+		verify_requested_features(&instance, physical_device, feature_config)?;
+		if let Some(size) = builder.subgroup_size {
+			verify_subgroup_size(&instance, physical_device, size)?;
+		}
+
+		let does_have_portability_subset_extension =
+			has_portability_subset_extension(&instance, physical_device)?;
+		let mut extensions = if does_have_portability_subset_extension {
+			vec![VK_KHR_PORTABILITY_SUBSET]
+		} else {
+			Vec::new()
+		};
+		if feature_config.shader_int8 || feature_config.shader_float16 {
+			extensions.push(vk::KHR_SHADER_FLOAT16_INT8_EXTENSION.name);
+		}
+		if feature_config.buffer_device_address {
+			extensions.push(vk::KHR_BUFFER_DEVICE_ADDRESS_EXTENSION.name);
+		}
+		if builder.subgroup_size.is_some() {
+			extensions.push(vk::EXT_SUBGROUP_SIZE_CONTROL_EXTENSION.name);
+		}
+		if feature_config.shader_clock {
+			extensions.push(vk::KHR_SHADER_CLOCK_EXTENSION.name);
+		}
+		add_requested_extensions(
+			&mut extensions,
+			&feature_config.device_extensions,
+			&instance.enumerate_device_extension_properties(physical_device, None)?,
+			"device",
+		);
+		let extension_names = extensions.iter().map(|e| e.as_ptr()).collect::<Vec<_>>();
+
+		// shader_float64 and shader_int16 are core features, which are enabled
+		// wholesale below; the 8 and 16 bit ones have to be chained on separately
+		let mut float16_int8 = vk::PhysicalDeviceShaderFloat16Int8Features::builder()
+			.shader_int8(feature_config.shader_int8)
+			.shader_float16(feature_config.shader_float16)
+			.build();
+		let mut storage_16bit = vk::PhysicalDevice16BitStorageFeatures::builder()
+			.storage_buffer_16bit_access(true)
+			.build();
+		let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::builder()
+			.buffer_device_address(true)
+			.build();
+		let mut subgroup_size_control = vk::PhysicalDeviceSubgroupSizeControlFeaturesEXT::builder()
+			.subgroup_size_control(true)
+			.build();
+		let device_clock = feature_config.shader_clock
+			&& get_shader_clock_features(&instance, physical_device)?.shader_device_clock
+				== vk::TRUE;
+		if feature_config.shader_clock && !device_clock {
+			info!("device has no shaderDeviceClock, shaders only get the subgroup clock");
+		}
+		let mut shader_clock = vk::PhysicalDeviceShaderClockFeaturesKHR::builder()
+			.shader_subgroup_clock(true)
+			.shader_device_clock(device_clock)
+			.build();
+		let mut more_features = vk::PhysicalDeviceFeatures2::builder().build();
+		let features = instance.get_physical_device_features(physical_device);
+
+		let mut device_create_info_partial = vk::DeviceCreateInfo::builder()
+			.queue_create_infos(&queue_infos)
+			.enabled_layer_names(&layers)
+			.enabled_extension_names(&extension_names);
+
+		if feature_config.shader_int8 || feature_config.shader_float16 {
+			device_create_info_partial = device_create_info_partial.push_next(&mut float16_int8);
+		}
+		if feature_config.storage_buffer_16bit_access {
+			device_create_info_partial = device_create_info_partial.push_next(&mut storage_16bit);
+		}
+		if feature_config.buffer_device_address {
+			device_create_info_partial =
+				device_create_info_partial.push_next(&mut buffer_device_address);
+		}
+		if builder.subgroup_size.is_some() {
+			device_create_info_partial =
+				device_create_info_partial.push_next(&mut subgroup_size_control);
+		}
+		if feature_config.shader_clock {
+			device_create_info_partial = device_create_info_partial.push_next(&mut shader_clock);
+		}
+
+		let device_create_info = if does_have_portability_subset_extension {
+			//required for shim'd Vulkan spec implementations, like MoltenVK
+			instance.get_physical_device_features2(physical_device, &mut more_features);
+			device_create_info_partial
+				.push_next(&mut more_features)
+				.build()
+		} else {
+			device_create_info_partial
+				.enabled_features(&features)
+				.build()
+		};
+
+		let logical_device = {
+			let _phase = phase!("create_device");
+			instance
+				.create_device(physical_device, &device_create_info, None)
+				.map_err(ComputeError::Vulkan)?
+		};
+		setup_timings.end("device");
+
+		let mut app = Self {
+			entry,
+			instance,
+			physical_device,
+			logical_device,
+			queue_index: compute_queue_index,
+			timestamp_valid_bits,
+			transfer_queue_index,
+			buffer_sharing: builder.buffer_sharing,
+			memory_index: 0,
+			memory_heap_index: 0,
+			memory_flags: vk::MemoryPropertyFlags::empty(),
+			memory: BufferMemory::Shared(vk::DeviceMemory::null()),
+			compute_shader: vk::ShaderModule::null(),
+			done_fence: vk::Fence::null(),
+			element_count: builder.element_count,
+			element_type: builder.element_type,
+			element_stride: builder.element_stride,
+			element_offset: builder.element_offset,
+			group_counts,
+			local_size: builder.local_size,
+			subgroup_size: builder.subgroup_size,
+			buffer_device_address: feature_config.buffer_device_address,
+			buffer_usage: builder.buffer_usage,
+			portability_subset: does_have_portability_subset_extension,
+			limits,
+			debug_messenger: None,
+			entry_point,
+			in_buffer: vk::Buffer::null(),
+			out_buffer: vk::Buffer::null(),
+			buffer_size,
+			buffer_stride,
+			setup_timings,
+		};
+		match app.create_resources(&builder.shader_bytes, &builder.memory_candidates()) {
+			Ok(()) => Ok(app),
+			Err(e) => {
+				app.destroy_resources();
+				app.logical_device.destroy_device(None);
+				Err(e)
+			}
+		}
+	}
+
+	/// The shader module, the input and output buffers with their memory and the done
+	/// fence, each stored as soon as it exists so a failure can free what came before.
+	/// The buffers' memory type is the first with any of `memory_candidates` that fits.
+	unsafe fn create_resources(
+		&mut self,
+		shader_bytes: &[u8],
+		memory_candidates: &[vk::MemoryPropertyFlags],
+	) -> Result<()> {
+		self.compute_shader = create_shader_module(&self.logical_device, shader_bytes)?;
+		self.setup_timings.end("shader module");
+
+		let memory_propertes = self
+			.instance
+			.get_physical_device_memory_properties(self.physical_device);
+		let desired_size = NUM_BUFFERS as vk::DeviceSize * self.buffer_stride();
+
+		let heap_sizes = get_heap_sizes(&self.instance, self.physical_device)?;
+
+		let memory_index: u32 = get_first_memory_type_index(
+			&memory_propertes,
+			&heap_sizes,
+			memory_candidates,
+			desired_size as usize,
+		)
+		.with_context(|| {
+			format!(
+				"couldn't fit {} buffers of {} {:?} elements in host-visible memory",
+				NUM_BUFFERS, self.element_count, self.element_type
+			)
+		})?;
+		let memory_type = memory_propertes.memory_types[memory_index as usize];
+		let memory_heap = memory_propertes.memory_heaps[memory_type.heap_index as usize];
+		info!(
+			"memory type {} lives in heap {} of {} MiB ({:?}), flags {:?}",
+			memory_index,
+			memory_type.heap_index,
+			memory_heap.size / (1024 * 1024),
+			memory_heap.flags,
+			memory_type.property_flags
+		);
+		let preferred = memory_candidates[0];
+		if memory_candidates.len() > 1 && !memory_type.property_flags.contains(preferred) {
+			warn!(
+				"no memory with {:?} fits the buffers, using {:?}",
+				preferred, memory_type.property_flags
+			);
+		}
+		self.memory_index = memory_index;
+		self.memory_heap_index = memory_type.heap_index;
+		self.memory_flags = memory_type.property_flags;
+
+		(self.memory, self.in_buffer, self.out_buffer) = {
+			let _phase = phase!("allocate_memory");
+			create_buffer_pair(
+				&self.logical_device,
+				memory_index,
+				(&heap_sizes, memory_type.heap_index),
+				self.buffer_size(),
+				self.buffer_stride(),
+				self.buffer_device_address,
+				self.buffer_usage,
+				&self.buffer_queue_families(),
+			)?
+		};
+
+		let fence_create = vk::FenceCreateInfo::builder()
+			.flags(vk::FenceCreateFlags::SIGNALED)
+			.build();
+		self.done_fence = self.logical_device.create_fence(&fence_create, None)?;
+		self.setup_timings.end("buffers");
+
+		Ok(())
+	}
+
+	/// Undoes [`App::create_resources`], however far it got.
+	unsafe fn destroy_resources(&self) {
+		self.logical_device
+			.destroy_shader_module(self.compute_shader, None);
+		self.logical_device.destroy_buffer(self.in_buffer, None);
+		self.logical_device.destroy_buffer(self.out_buffer, None);
+		for memory in self.memory.allocations() {
+			self.logical_device.free_memory(*memory, None);
+		}
+		self.logical_device.destroy_fence(self.done_fence, None);
+	}
+
+	/// The compute family, and the transfer family when there's a separate one.
+	pub fn queue_families(&self) -> Vec<u32> {
+		std::iter::once(self.queue_index)
+			.chain(self.transfer_queue_index)
+			.collect()
+	}
+
+	/// The families the input and output buffers are created for: every one in use
+	/// when they're shared concurrently, none when they're exclusive.
+	fn buffer_queue_families(&self) -> Vec<u32> {
+		match self.buffer_sharing {
+			BufferSharing::Concurrent => self.queue_families(),
+			BufferSharing::Exclusive => Vec::new(),
+		}
+	}
+
+	/// The transfer family a staged upload has to hand the input buffer over from,
+	/// which there is only with exclusive buffers.
+	fn ownership_transfer_family(&self) -> Option<u32> {
+		match self.buffer_sharing {
+			BufferSharing::Concurrent => None,
+			BufferSharing::Exclusive => self.transfer_queue_index,
+		}
+	}
+
+	/// Where the output buffer starts in `memory`, and how far apart the two buffers are.
+	pub fn buffer_stride(&self) -> vk::DeviceSize {
+		self.buffer_stride
+	}
+
+	/// The bytes one of the input or output buffers actually holds.
+	pub fn buffer_size(&self) -> vk::DeviceSize {
+		self.buffer_size
+	}
+
+	/// Fills the input buffer with `data`, which has to be exactly `element_count`
+	/// floats; [`crate::input::generate_ramp`] makes the old default.
+	pub unsafe fn populate_buffer(&mut self, data: &[f32]) -> Result<()> {
+		self.write_input(data)
+	}
+
+	/// Copies exactly `element_count` floats into the input buffer.
+	pub unsafe fn write_input(&self, floats: &[f32]) -> Result<()> {
+		self.write_floats(self.memory, floats)
+	}
+
+	/// Copies exactly `element_count` floats into the input buffer in `memory`.
+	unsafe fn write_floats(&self, memory: BufferMemory, floats: &[f32]) -> Result<()> {
+		let (memory, offset) = memory.location(0, self.buffer_stride());
+		self.write_floats_at(memory, offset, floats)
+	}
+
+	/// Copies exactly `element_count` floats into `memory` at `offset`.
+	unsafe fn write_floats_at(
+		&self,
+		memory: vk::DeviceMemory,
+		offset: vk::DeviceSize,
+		floats: &[f32],
+	) -> Result<()> {
+		if floats.len() != self.element_count {
+			return Err(anyhow!(
+				"got {} input values for {} elements",
+				floats.len(),
+				self.element_count
+			));
+		}
+
+		let bytes = self.element_type.encode(&self.spread_elements(floats));
+		let (mapped, range) = self.map_buffer_memory(memory, offset, self.buffer_size())?;
+
+		// in chunks of whole elements, so a progress bar can follow along
+		let element_size = self.element_type.size();
+		let chunk_size = element_size << 16;
+		let progress = Progress::new("uploading input", bytes.len() / element_size);
+		for (i, chunk) in bytes.chunks(chunk_size).enumerate() {
+			progress.at(i * chunk_size / element_size);
+			memcpy(chunk.as_ptr(), mapped.add(i * chunk_size), chunk.len());
+		}
+		progress.finish();
+
+		if !self.is_host_coherent() {
+			self.logical_device.flush_mapped_memory_ranges(&[range])?;
+		}
+		self.logical_device.unmap_memory(memory);
+
+		Ok(())
+	}
+
+	/// `floats` at their positions in a buffer, with zeros in the gaps between them when
+	/// they have an `element_stride`.
+	fn spread_elements<'a>(&self, floats: &'a [f32]) -> Cow<'a, [f32]> {
+		if self.element_stride == 1 {
+			return Cow::Borrowed(floats);
+		}
+		let stride = self.element_stride as usize;
+		let mut positions = vec![0.0; floats.len() * stride];
+		for (i, value) in floats.iter().enumerate() {
+			positions[i * stride + self.element_offset as usize] = *value;
+		}
+		Cow::Owned(positions)
+	}
+
+	/// Whether the input and output buffers' writes and reads need no flushing or
+	/// invalidating.
+	pub fn is_host_coherent(&self) -> bool {
+		self.memory_flags
+			.contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+	}
+
+	/// Maps `size` bytes of `memory` at `offset`. Without `HOST_COHERENT` the mapping
+	/// starts a little earlier, on a `nonCoherentAtomSize` boundary, and runs to the end
+	/// of the allocation, so the range handed back can be flushed or invalidated as is.
+	/// Either way the pointer is to `offset`.
+	unsafe fn map_buffer_memory(
+		&self,
+		memory: vk::DeviceMemory,
+		offset: vk::DeviceSize,
+		size: vk::DeviceSize,
+	) -> Result<(*mut u8, vk::MappedMemoryRange)> {
+		let (start, size) = if self.is_host_coherent() {
+			(offset, size)
+		} else {
+			let atom = self.limits.non_coherent_atom_size.max(1);
+			(offset - offset % atom, vk::WHOLE_SIZE as vk::DeviceSize)
+		};
+		let mapped =
+			self.logical_device
+				.map_memory(memory, start, size, vk::MemoryMapFlags::empty())?;
+		let range = vk::MappedMemoryRange::builder()
+			.memory(memory)
+			.offset(start)
+			.size(size)
+			.build();
+		Ok((mapped.cast::<u8>().add((offset - start) as usize), range))
+	}
+
+	/// The input and output buffers, and a layout for each set from 0 up to the highest
+	/// one in `bindings`, those without bindings empty.
+	pub unsafe fn bind_buffer_layout(
+		&mut self,
+		bindings: &[DescriptorBinding],
+	) -> Result<(vk::Buffer, vk::Buffer, Vec<vk::DescriptorSetLayout>)> {
+		let set_count = set_count(bindings);
+		if set_count > self.limits.max_bound_descriptor_sets {
+			return Err(anyhow!(
+				"bindings use descriptor sets up to {}, the device binds at most {}",
+				set_count - 1,
+				self.limits.max_bound_descriptor_sets
+			));
+		}
+
+		let mut layouts = Vec::with_capacity(set_count as usize);
+		for set in 0..set_count {
+			let set_bindings = bindings
+				.iter()
+				.filter(|b| b.set == set)
+				.copied()
+				.collect::<Vec<_>>();
+			let set_bindings = layout_bindings(&set_bindings);
+			let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&set_bindings);
+			match self
+				.logical_device
+				.create_descriptor_set_layout(&info, None)
+			{
+				Ok(layout) => layouts.push(layout),
+				Err(e) => {
+					for layout in layouts {
+						self.logical_device
+							.destroy_descriptor_set_layout(layout, None);
+					}
+					return Err(e.into());
+				}
+			}
+		}
+
+		Ok((self.in_buffer, self.out_buffer, layouts))
+	}
+
+	/// Allocates a set for each of `layouts`, and a second one of `descriptor_set`, the
+	/// set holding the input and output buffers: the first reads `in_buffer` and writes
+	/// `out_buffer`, the second the other way round, so chained passes can ping-pong.
+	/// Hands back the sets to bind for even and for odd passes, which differ only there.
+	/// `extra_buffers` go in whichever set they belong to. The pool is sized for
+	/// `bindings`, the ones the layouts were built from.
+	pub unsafe fn create_descriptor_pool_and_set(
+		&self,
+		in_buffer: &vk::Buffer,
+		out_buffer: &vk::Buffer,
+		layouts: &[vk::DescriptorSetLayout],
+		descriptor_set: u32,
+		bindings: &[DescriptorBinding],
+		extra_buffers: &[ExtraBuffer],
+	) -> Result<(vk::DescriptorPool, [Vec<vk::DescriptorSet>; 2])> {
+		// every set but the main one is allocated once, so this is a little generous
+		let pool_sizes = pool_sizes(bindings, 2);
+		let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+			.max_sets(layouts.len() as u32 + 1)
+			.pool_sizes(&pool_sizes)
+			.build();
+		let descriptor_pool = self
+			.logical_device
+			.create_descriptor_pool(&pool_create_info, None)?;
+
+		let layout_wrapper = ping_pong_layouts(layouts, descriptor_set);
+		let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+			.descriptor_pool(descriptor_pool)
+			.set_layouts(&layout_wrapper)
+			.build();
+
+		let sets = match self.logical_device.allocate_descriptor_sets(&allocate_info) {
+			Ok(sets) => sets,
+			Err(e) => {
+				self.logical_device
+					.destroy_descriptor_pool(descriptor_pool, None);
+				return Err(e.into());
+			}
+		};
+
+		let descriptor_sets =
+			self.write_ping_pong_sets(sets, in_buffer, out_buffer, descriptor_set, extra_buffers);
+		Ok((descriptor_pool, descriptor_sets))
+	}
+
+	/// Fills in `sets`, allocated for [`ping_pong_layouts`], and splits them into the
+	/// ones to bind for even and for odd passes.
+	unsafe fn write_ping_pong_sets(
+		&self,
+		mut sets: Vec<vk::DescriptorSet>,
+		in_buffer: &vk::Buffer,
+		out_buffer: &vk::Buffer,
+		descriptor_set: u32,
+		extra_buffers: &[ExtraBuffer],
+	) -> [Vec<vk::DescriptorSet>; 2] {
+		let main = descriptor_set as usize;
+		let swapped = sets.pop().expect("one set was allocated per layout");
+		let even_sets = sets;
+		let mut odd_sets = even_sets.clone();
+		odd_sets[main] = swapped;
+
+		for (set, descriptor_set) in even_sets.iter().enumerate() {
+			let set_extra_buffers = extra_buffers
+				.iter()
+				.filter(|extra| extra.set == set as u32)
+				.copied()
+				.collect::<Vec<_>>();
+			let buffers = (set == main).then_some((in_buffer, out_buffer));
+			self.write_descriptor_set(*descriptor_set, buffers, &set_extra_buffers);
+			if set == main {
+				let buffers = Some((out_buffer, in_buffer));
+				self.write_descriptor_set(swapped, buffers, &set_extra_buffers);
+			}
+		}
+
+		[even_sets, odd_sets]
+	}
+
+	/// `buffers` are the ones read and written at bindings 0 and 1, for the set that
+	/// has them.
+	unsafe fn write_descriptor_set(
+		&self,
+		descriptor_set: vk::DescriptorSet,
+		buffers: Option<(&vk::Buffer, &vk::Buffer)>,
+		extra_buffers: &[ExtraBuffer],
+	) {
+		let buffer_infos = buffers
+			.into_iter()
+			.flat_map(|(in_buffer, out_buffer)| [in_buffer, out_buffer])
+			.map(|buffer| {
+				[vk::DescriptorBufferInfo {
+					buffer: *buffer,
+					offset: 0,
+					range: vk::WHOLE_SIZE as vk::DeviceSize,
+				}]
+			})
+			.collect::<Vec<_>>();
+
+		let extra_buffer_infos = extra_buffers
+			.iter()
+			.map(|extra| {
+				[vk::DescriptorBufferInfo {
+					buffer: extra.buffer,
+					offset: extra.offset,
+					range: extra.range,
+				}]
+			})
+			.collect::<Vec<_>>();
+
+		// the input and output buffers at 0 and 1, if they're in this set
+		let mut write_sets = buffer_infos
+			.iter()
+			.enumerate()
+			.map(|(binding, info)| {
+				vk::WriteDescriptorSet::builder()
+					.dst_set(descriptor_set)
+					.dst_binding(binding as u32)
+					.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+					.buffer_info(info)
+					.build()
+			})
+			.collect::<Vec<_>>();
+		write_sets.extend(extra_buffers.iter().zip(&extra_buffer_infos).map(
+			|(extra, buffer_info)| {
+				vk::WriteDescriptorSet::builder()
+					.dst_set(descriptor_set)
+					.dst_binding(extra.binding)
+					.descriptor_type(extra.kind.descriptor_type())
+					.buffer_info(buffer_info)
+					.build()
+			},
+		));
+
+		self.logical_device
+			.update_descriptor_sets(&write_sets, &[] as &[vk::CopyDescriptorSet]);
+	}
+
+	/// `push_constant_size` is what the shaders declare, grown to fit the buffer addresses
+	/// when those are pushed.
+	pub unsafe fn create_pipeine_with_layout(
+		&mut self,
+		descriptor_layouts: &[vk::DescriptorSetLayout],
+		push_constant_size: u32,
+	) -> Result<(vk::Pipeline, vk::PipelineLayout)> {
+		let push_constant_size = if self.buffer_device_address {
+			push_constant_size.max(size_of::<[vk::DeviceAddress; 2]>() as u32)
+		} else {
+			push_constant_size
+		};
+		let push_constant_ranges = if push_constant_size > 0 {
+			vec![vk::PushConstantRange {
+				stage_flags: vk::ShaderStageFlags::COMPUTE,
+				offset: 0,
+				size: push_constant_size,
+			}]
+		} else {
+			Vec::new()
+		};
+
+		let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+			.set_layouts(descriptor_layouts)
+			.push_constant_ranges(&push_constant_ranges)
+			.build();
+
+		let pipeline_layout = self
+			.logical_device
+			.create_pipeline_layout(&pipeline_layout_create_info, None)?;
+
+		let pipeline = self.create_pipeline(&pipeline_layout, self.compute_shader, &[])?;
+
+		Ok((pipeline, pipeline_layout))
+	}
+
+	/// `specialization` supplies u32 values for `constant_id` 0, 1, 2...
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+	pub unsafe fn create_pipeline(
+		&self,
+		pipeline_layout: &vk::PipelineLayout,
+		shader: vk::ShaderModule,
+		specialization: &[u32],
+	) -> Result<vk::Pipeline> {
+		let map_entries = (0..specialization.len())
+			.map(|i| vk::SpecializationMapEntry {
+				constant_id: i as u32,
+				offset: (i * size_of::<u32>()) as u32,
+				size: size_of::<u32>(),
+			})
+			.collect::<Vec<_>>();
+		let data = specialization
+			.iter()
+			.flat_map(|value| value.to_ne_bytes())
+			.collect::<Vec<_>>();
+		let specialization_info = vk::SpecializationInfo::builder()
+			.map_entries(&map_entries)
+			.data(&data)
+			.build();
+
+		let mut stage = vk::PipelineShaderStageCreateInfo::builder()
+			.stage(vk::ShaderStageFlags::COMPUTE)
+			.module(shader)
+			.name(self.entry_point.as_bytes_with_nul())
+			.specialization_info(&specialization_info)
+			.build();
+		let required_subgroup_size =
+			vk::PipelineShaderStageRequiredSubgroupSizeCreateInfoEXT::builder()
+				.required_subgroup_size(self.subgroup_size.unwrap_or_default())
+				.build();
+		// vulkanalia doesn't know this extends the stage, so chain it by hand
+		if self.subgroup_size.is_some() {
+			stage.next = &required_subgroup_size as *const _ as *const std::ffi::c_void;
+		}
+
+		let compute_pipeline_create_info = vk::ComputePipelineCreateInfo::builder()
+			.stage(stage)
+			.layout(*pipeline_layout)
+			.build();
+
+		let (pipeline, code) = self.logical_device.create_compute_pipelines(
+			vk::PipelineCache::default(),
+			&[compute_pipeline_create_info],
+			None,
+		)?;
+		// success codes other than SUCCESS, like PIPELINE_COMPILE_REQUIRED, mean there's
+		// no pipeline after all
+		if code != vk::SuccessCode::SUCCESS || pipeline.is_null() {
+			if !pipeline.is_null() {
+				self.logical_device.destroy_pipeline(pipeline, None);
+			}
+			return Err(anyhow!(
+				"creating the compute pipeline returned {:?} without a usable pipeline",
+				code
+			));
+		}
+
+		Ok(pipeline)
+	}
+
+	/// One pipeline per configured pass, all sharing the two-buffer pipeline layout. If
+	/// one fails, those already created are destroyed again.
+	pub unsafe fn create_pass_pipelines(
+		&self,
+		pipeline_layout: &vk::PipelineLayout,
+		passes: &[PassConfig],
+	) -> Result<Vec<vk::Pipeline>> {
+		let mut pipelines = Vec::with_capacity(passes.len());
+		for pass in passes {
+			match self.create_pass_pipeline(pipeline_layout, pass) {
+				Ok(pipeline) => pipelines.push(pipeline),
+				Err(e) => {
+					for pipeline in pipelines {
+						self.logical_device.destroy_pipeline(pipeline, None);
+					}
+					return Err(e);
+				}
+			}
+		}
+		Ok(pipelines)
+	}
+
+	unsafe fn create_pass_pipeline(
+		&self,
+		pipeline_layout: &vk::PipelineLayout,
+		pass: &PassConfig,
+	) -> Result<vk::Pipeline> {
+		let bytecode = load_shader(&pass.shader)?;
+		let shader = create_shader_module(&self.logical_device, &bytecode)?;
+		let pipeline = self.create_pipeline(pipeline_layout, shader, &pass.specialization);
+		// a pipeline keeps what it needs from the module
+		self.logical_device.destroy_shader_module(shader, None);
+		pipeline
+	}
+
+	pub unsafe fn create_command_pool_and_buffer(
+		&mut self,
+	) -> Result<(vk::CommandPool, vk::CommandBuffer)> {
+		let command_pool_create_info = vk::CommandPoolCreateInfo::builder()
+			.flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+			.queue_family_index(self.queue_index)
+			.build();
+		let command_pool = self
+			.logical_device
+			.create_command_pool(&command_pool_create_info, None)?;
+
+		let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::builder()
+			.command_pool(command_pool)
+			.level(vk::CommandBufferLevel::PRIMARY)
+			.command_buffer_count(1)
+			.build();
+
+		let mut command_buffers = self
+			.logical_device
+			.allocate_command_buffers(&command_buffer_alloc_info)?;
+
+		Ok((command_pool, command_buffers.remove(0)))
+	}
+
+	/// The input and output buffers' addresses, in the order the first pass sees them.
+	pub unsafe fn get_buffer_addresses(
+		&self,
+		in_buffer: &vk::Buffer,
+		out_buffer: &vk::Buffer,
+	) -> [vk::DeviceAddress; 2] {
+		[in_buffer, out_buffer].map(|buffer| {
+			let info = vk::BufferDeviceAddressInfo::builder().buffer(*buffer);
+			self.logical_device.get_buffer_device_address_khr(&info)
+		})
+	}
+
+	/// A one-query pool counting compute shader invocations, or `None` with a warning
+	/// when the device doesn't support pipeline statistics queries.
+	pub unsafe fn create_statistics_query_pool(&self) -> Result<Option<vk::QueryPool>> {
+		let features = self
+			.instance
+			.get_physical_device_features(self.physical_device);
+		if features.pipeline_statistics_query != vk::TRUE {
+			warn!("pipeline statistics queries aren't supported, not counting invocations");
+			return Ok(None);
+		}
+
+		let info = vk::QueryPoolCreateInfo::builder()
+			.query_type(vk::QueryType::PIPELINE_STATISTICS)
+			.query_count(1)
+			.pipeline_statistics(vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS)
+			.build();
+		Ok(Some(self.logical_device.create_query_pool(&info, None)?))
+	}
+
+	/// What the compute queue's family advertises.
+	pub unsafe fn compute_queue_flags(&self) -> vk::QueueFlags {
+		self.instance
+			.get_physical_device_queue_family_properties(self.physical_device)[self.queue_index as usize]
+			.queue_flags
+	}
+
+	/// A pool of `count` timestamps, or none with a warning when the compute queue
+	/// can't write them.
+	pub unsafe fn create_timestamp_query_pool(&self, count: u32) -> Result<Option<vk::QueryPool>> {
+		if self.timestamp_valid_bits == 0 {
+			warn!("the compute queue can't write timestamps, not timing passes");
+			return Ok(None);
+		}
+
+		let info = vk::QueryPoolCreateInfo::builder()
+			.query_type(vk::QueryType::TIMESTAMP)
+			.query_count(count)
+			.build();
+		Ok(Some(self.logical_device.create_query_pool(&info, None)?))
+	}
+
+	/// An event for each of the `count` boundaries between passes, or none with a warning
+	/// on portability devices that can't do events, which then get barriers instead.
+	pub unsafe fn create_pass_events(&self, count: usize) -> Result<Vec<vk::Event>> {
+		if self.portability_subset {
+			let portability = get_portability_subset_info(&self.instance, self.physical_device)?;
+			if portability.is_some_and(|p| p.unsupported.iter().any(|name| name == "events")) {
+				warn!("the device's portability subset has no events, using barriers");
+				return Ok(Vec::new());
+			}
+		}
+
+		let mut events = Vec::with_capacity(count);
+		for _ in 0..count {
+			match self
+				.logical_device
+				.create_event(&vk::EventCreateInfo::default(), None)
+			{
+				Ok(event) => events.push(event),
+				Err(e) => {
+					self.destroy_pass_events(&events);
+					return Err(e.into());
+				}
+			}
+		}
+		Ok(events)
+	}
+
+	/// A staging buffer and a command buffer on the transfer family copying it into the
+	/// input buffer, or `None` with a warning when there's no separate transfer queue,
+	/// in which case the input is written directly as usual.
+	pub unsafe fn create_staged_upload(&self) -> Result<Option<StagedUpload>> {
+		let Some(transfer_queue_index) = self.transfer_queue_index else {
+			warn!("no separate transfer queue, writing the input directly");
+			return Ok(None);
+		};
+
+		let mut staged = StagedUpload {
+			buffer: vk::Buffer::null(),
+			memory: vk::DeviceMemory::null(),
+			command_pool: vk::CommandPool::null(),
+			command_buffer: vk::CommandBuffer::null(),
+			semaphore: vk::Semaphore::null(),
+		};
+		match self.fill_staged_upload(&mut staged, transfer_queue_index) {
+			Ok(()) => Ok(Some(staged)),
+			Err(e) => {
+				self.destroy_staged_upload(&staged);
+				Err(e)
+			}
+		}
+	}
+
+	unsafe fn fill_staged_upload(
+		&self,
+		staged: &mut StagedUpload,
+		transfer_queue_index: u32,
+	) -> Result<()> {
+		(staged.buffer, staged.memory) = self
+			.create_host_visible_buffer(self.buffer_size(), vk::BufferUsageFlags::TRANSFER_SRC)?;
+
+		let command_pool_create_info = vk::CommandPoolCreateInfo::builder()
+			.queue_family_index(transfer_queue_index)
+			.build();
+		staged.command_pool = self
+			.logical_device
+			.create_command_pool(&command_pool_create_info, None)?;
+		let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::builder()
+			.command_pool(staged.command_pool)
+			.level(vk::CommandBufferLevel::PRIMARY)
+			.command_buffer_count(1)
+			.build();
+		staged.command_buffer = self
+			.logical_device
+			.allocate_command_buffers(&command_buffer_alloc_info)?
+			.remove(0);
+
+		// shared concurrently, the input buffer needs no ownership transfer and the
+		// semaphore makes the copy visible to the compute. Exclusive, the copy overwrites
+		// all of it, so whatever the compute family left can be dropped rather than
+		// released back first
+		self.logical_device.begin_command_buffer(
+			staged.command_buffer,
+			&vk::CommandBufferBeginInfo::default(),
+		)?;
+		let region = vk::BufferCopy::builder().size(self.buffer_size()).build();
+		self.logical_device.cmd_copy_buffer(
+			staged.command_buffer,
+			staged.buffer,
+			self.in_buffer,
+			&[region],
+		);
+		// the release half of handing the input buffer to the compute family; the
+		// dispatch records the acquire, after waiting on the semaphore
+		if let Some(transfer_queue_index) = self.ownership_transfer_family() {
+			let release = self.input_ownership_transfer(
+				transfer_queue_index,
+				vk::AccessFlags::TRANSFER_WRITE,
+				vk::AccessFlags::empty(),
+			);
+			self.logical_device.cmd_pipeline_barrier(
+				staged.command_buffer,
+				vk::PipelineStageFlags::TRANSFER,
+				vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+				vk::DependencyFlags::empty(),
+				&[] as &[vk::MemoryBarrier],
+				&[release],
+				&[] as &[vk::ImageMemoryBarrier],
+			);
+		}
+		self.logical_device
+			.end_command_buffer(staged.command_buffer)?;
+
+		staged.semaphore = self
+			.logical_device
+			.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
+		Ok(())
+	}
+
+	/// A barrier moving the input buffer from the transfer family to the compute family,
+	/// recorded once on each side with the same families: the release on the transfer
+	/// queue with only `src_access`, the acquire on the compute queue with only
+	/// `dst_access`.
+	fn input_ownership_transfer(
+		&self,
+		transfer_queue_index: u32,
+		src_access: vk::AccessFlags,
+		dst_access: vk::AccessFlags,
+	) -> vk::BufferMemoryBarrier {
+		vk::BufferMemoryBarrier::builder()
+			.src_access_mask(src_access)
+			.dst_access_mask(dst_access)
+			.src_queue_family_index(transfer_queue_index)
+			.dst_queue_family_index(self.queue_index)
+			.buffer(self.in_buffer)
+			.offset(0)
+			.size(vk::WHOLE_SIZE as vk::DeviceSize)
+			.build()
+	}
+
+	/// Fills the staging buffer with `floats` and submits the copy into the input
+	/// buffer, which signals `staged.semaphore` once done. Nothing waits for it here;
+	/// the compute submit does.
+	pub unsafe fn write_staged_input(&self, staged: &StagedUpload, floats: &[f32]) -> Result<()> {
+		self.write_floats_at(staged.memory, 0, floats)?;
+
+		let _phase = phase!("upload");
+		let transfer_queue_index = self
+			.transfer_queue_index
+			.ok_or_else(|| anyhow!("staged uploads need a transfer queue"))?;
+		let queue = self
+			.logical_device
+			.get_device_queue(transfer_queue_index, 0);
+		let command_buffers = &[staged.command_buffer];
+		let signal_semaphores = &[staged.semaphore];
+		let submit_info = vk::SubmitInfo::builder()
+			.command_buffers(command_buffers)
+			.signal_semaphores(signal_semaphores)
+			.build();
+		self.logical_device
+			.queue_submit(queue, &[submit_info], vk::Fence::null())
+			.map_err(|e| device_error(e, "submitting the upload"))?;
+		Ok(())
+	}
+
+	/// The copy must be done with, which it is once a compute submit waiting on it is.
+	pub unsafe fn destroy_staged_upload(&self, staged: &StagedUpload) {
+		self.logical_device
+			.destroy_semaphore(staged.semaphore, None);
+		self.logical_device
+			.destroy_command_pool(staged.command_pool, None);
+		self.logical_device.destroy_buffer(staged.buffer, None);
+		self.logical_device.free_memory(staged.memory, None);
+	}
+
+	pub unsafe fn destroy_pass_events(&self, events: &[vk::Event]) {
+		for event in events {
+			self.logical_device.destroy_event(*event, None);
+		}
+	}
+
+	/// Unsignals `events` so the next recording's waits block until their sets. Only
+	/// safe once nothing that uses them is still running.
+	pub unsafe fn reset_pass_events(&self, events: &[vk::Event]) -> Result<()> {
+		for event in events {
+			self.logical_device.reset_event(*event)?;
+		}
+		Ok(())
+	}
+
+	/// Only meaningful once the dispatch recorded with `query_pool` has finished.
+	pub unsafe fn get_invocation_count(&self, query_pool: &vk::QueryPool) -> Result<u64> {
+		let mut data = [0; size_of::<u64>()];
+		self.logical_device.get_query_pool_results(
+			*query_pool,
+			0,
+			1,
+			&mut data,
+			size_of::<u64>() as vk::DeviceSize,
+			vk::QueryResultFlags::_64 | vk::QueryResultFlags::WAIT,
+		)?;
+		Ok(u64::from_ne_bytes(data))
+	}
+
+	/// The time between each of the first `count` timestamps in `query_pool` and the
+	/// next, so one fewer than `count`. Only meaningful once the dispatch recorded with
+	/// it has finished.
+	pub unsafe fn get_timestamp_durations(
+		&self,
+		query_pool: &vk::QueryPool,
+		count: u32,
+	) -> Result<Vec<Duration>> {
+		let mut data = vec![0; count as usize * size_of::<u64>()];
+		self.logical_device.get_query_pool_results(
+			*query_pool,
+			0,
+			count,
+			&mut data,
+			size_of::<u64>() as vk::DeviceSize,
+			vk::QueryResultFlags::_64 | vk::QueryResultFlags::WAIT,
+		)?;
+
+		// bits above the valid ones are undefined, and the counter may wrap between two
+		let mask = u64::MAX >> (64 - self.timestamp_valid_bits.clamp(1, 64));
+		let ticks = data
+			.chunks_exact(size_of::<u64>())
+			.map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap()) & mask)
+			.collect::<Vec<_>>();
+		let period = self.limits.timestamp_period as f64;
+		Ok(ticks
+			.windows(2)
+			.map(|pair| {
+				let elapsed = pair[1].wrapping_sub(pair[0]) & mask;
+				Duration::from_nanos((elapsed as f64 * period) as u64)
+			})
+			.collect())
+	}
+
+	/// How many invocations `pass_count` passes should add up to, one per local
+	/// invocation of every dispatched workgroup.
+	pub fn expected_invocations(&self, pass_count: usize) -> u64 {
+		let per_pass = self
+			.group_counts
+			.iter()
+			.chain(&self.local_size)
+			.map(|n| *n as u64)
+			.product::<u64>();
+		per_pass * pass_count as u64
+	}
+
+	/// Returns `command_buffer` to the initial state, ready to be recorded again.
+	pub unsafe fn reset_command_buffer(&self, command_buffer: &vk::CommandBuffer) -> Result<()> {
+		self.logical_device
+			.reset_command_buffer(*command_buffer, vk::CommandBufferResetFlags::empty())
+			.map_err(|e| device_error(e, "resetting the command buffer"))?;
+		Ok(())
+	}
+
+	/// A host-written `vk::DispatchIndirectCommand` for `cmd_dispatch_indirect`. It also
+	/// has storage usage so an earlier shader could overwrite the group counts.
+	pub unsafe fn create_indirect_buffer(&mut self) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+		let [x, y, z] = self.group_counts;
+		let command = vk::DispatchIndirectCommand { x, y, z };
+		let size = size_of::<vk::DispatchIndirectCommand>() as vk::DeviceSize;
+
+		let (buffer, memory) = self.create_host_visible_buffer(
+			size,
+			vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+		)?;
+
+		let mapped =
+			match self
+				.logical_device
+				.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+			{
+				Ok(mapped) => mapped,
+				Err(e) => {
+					self.logical_device.destroy_buffer(buffer, None);
+					self.logical_device.free_memory(memory, None);
+					return Err(e.into());
+				}
+			};
+		memcpy(&command, mapped.cast(), 1);
+		self.logical_device.unmap_memory(memory);
+
+		Ok((buffer, memory))
+	}
+
+	/// One small buffer per configured binding, each in its own allocation and filled
+	/// with the binding's `values`, zero-padded to `element_count`. Views get no buffer
+	/// of their own, just a window onto the one they view.
+	pub unsafe fn create_extra_buffers(
+		&self,
+		bindings: &[BindingConfig],
+		descriptor_set: u32,
+	) -> Result<Vec<ExtraBuffer>> {
+		let mut extra_buffers = Vec::with_capacity(bindings.len());
+		match self.push_extra_buffers(bindings, descriptor_set, &mut extra_buffers) {
+			Ok(()) => Ok(extra_buffers),
+			Err(e) => {
+				self.destroy_extra_buffers(&extra_buffers);
+				Err(e)
+			}
+		}
+	}
+
+	/// Creates `bindings`' buffers onto the end of `extra_buffers`, leaving the ones made
+	/// before a failing binding there for the caller to destroy.
+	unsafe fn push_extra_buffers(
+		&self,
+		bindings: &[BindingConfig],
+		descriptor_set: u32,
+		extra_buffers: &mut Vec<ExtraBuffer>,
+	) -> Result<()> {
+		// elements in each buffer so far, for checking views' windows
+		let mut element_counts = Vec::with_capacity(bindings.len());
+		for (i, binding) in bindings.iter().enumerate() {
+			let number = FIRST_EXTRA_BINDING + i as u32;
+			let set = binding.set.unwrap_or(descriptor_set);
+
+			if let Some(viewed) = binding.view_of {
+				let Some(source) = extra_buffers
+					.iter()
+					.position(|extra| extra.binding == viewed)
+				else {
+					return Err(anyhow!(
+						"binding {} views binding {}, which isn't an earlier extra binding",
+						number,
+						viewed
+					));
+				};
+				if extra_buffers[source].kind != binding.kind {
+					return Err(anyhow!(
+						"binding {} is {:?} but views {:?} binding {}",
+						number,
+						binding.kind,
+						extra_buffers[source].kind,
+						viewed
+					));
+				}
+				if !binding.values.is_empty()
+					|| binding.element_count != 0
+					|| !binding.usage.is_empty()
+				{
+					return Err(anyhow!(
+						"binding {} is a view, its values, element_count and usage come from binding {}",
+						number,
+						viewed
+					));
+				}
+				if binding.readback {
+					return Err(anyhow!(
+						"binding {} is a view, read back binding {} instead",
+						number,
+						viewed
+					));
+				}
+				let element_count = element_counts[source];
+				let (offset, range) = self.extra_buffer_window(number, binding, element_count)?;
+				extra_buffers.push(ExtraBuffer {
+					set,
+					binding: number,
+					kind: binding.kind,
+					buffer: extra_buffers[source].buffer,
+					memory: None,
+					offset,
+					range,
+					element_count,
+					readback: false,
+				});
+				element_counts.push(element_count);
+				continue;
+			}
+
+			if binding.element_count == 0 {
+				return Err(anyhow!("binding {} needs an element_count", number));
+			}
+			if binding.readback && binding.kind == BufferKind::Uniform {
+				return Err(anyhow!(
+					"binding {} is a uniform buffer, which shaders can't write, so there's nothing to read back",
+					number
+				));
+			}
+			if binding.values.len() > binding.element_count {
+				return Err(anyhow!(
+					"binding {} has {} values but only {} elements",
+					number,
+					binding.values.len(),
+					binding.element_count
+				));
+			}
+			let (offset, range) =
+				self.extra_buffer_window(number, binding, binding.element_count)?;
+
+			let size = checked_size(binding.element_count, size_of::<f32>())?;
+			let usage = match binding.kind {
+				BufferKind::Storage => vk::BufferUsageFlags::STORAGE_BUFFER,
+				BufferKind::Uniform => vk::BufferUsageFlags::UNIFORM_BUFFER,
+			} | BufferUsage::combined(&binding.usage);
+			let (buffer, memory) = self.create_host_visible_buffer(size, usage)?;
+
+			let mut values = binding.values.clone();
+			values.resize(binding.element_count, 0.0);
+			let mapped =
+				match self
+					.logical_device
+					.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+				{
+					Ok(mapped) => mapped,
+					Err(e) => {
+						self.logical_device.destroy_buffer(buffer, None);
+						self.logical_device.free_memory(memory, None);
+						return Err(e.into());
+					}
+				};
+			#[cfg(feature = "bytemuck")]
+			std::slice::from_raw_parts_mut(mapped.cast::<u8>(), size as usize)
+				.copy_from_slice(bytemuck::cast_slice(&values));
+			#[cfg(not(feature = "bytemuck"))]
+			memcpy(values.as_ptr(), mapped.cast(), values.len());
+			self.logical_device.unmap_memory(memory);
+
+			extra_buffers.push(ExtraBuffer {
+				set,
+				binding: number,
+				kind: binding.kind,
+				buffer,
+				memory: Some(memory),
+				offset,
+				range,
+				element_count: binding.element_count,
+				readback: binding.readback,
+			});
+			element_counts.push(binding.element_count);
+		}
+		Ok(())
+	}
+
+	/// Destroys the buffers and frees the memory `extra_buffers` own. Views share their
+	/// buffer with the binding that owns it, so they're skipped.
+	unsafe fn destroy_extra_buffers(&self, extra_buffers: &[ExtraBuffer]) {
+		for extra in extra_buffers {
+			if let Some(memory) = extra.memory {
+				self.logical_device.destroy_buffer(extra.buffer, None);
+				self.logical_device.free_memory(memory, None);
+			}
+		}
+	}
+
+	/// The whole of `extra`'s buffer, which has to have memory of its own. The memory is
+	/// host-coherent, so there's nothing to invalidate.
+	pub unsafe fn read_extra_buffer(&self, extra: &ExtraBuffer) -> Result<Vec<f32>> {
+		let Some(memory) = extra.memory else {
+			return Err(anyhow!(
+				"binding {} is a view without memory of its own",
+				extra.binding
+			));
+		};
+		let size = checked_size(extra.element_count, size_of::<f32>())?;
+		let mapped =
+			self.logical_device
+				.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())?;
+		let mut values = vec![0.0f32; extra.element_count];
+		memcpy(
+			mapped.cast_const().cast(),
+			values.as_mut_ptr(),
+			values.len(),
+		);
+		self.logical_device.unmap_memory(memory);
+		Ok(values)
+	}
+
+	/// The byte offset and range of `binding`'s window onto a buffer of `element_count`
+	/// floats, checked against the buffer's end and the device's offset alignment.
+	fn extra_buffer_window(
+		&self,
+		number: u32,
+		binding: &BindingConfig,
+		element_count: usize,
+	) -> Result<(vk::DeviceSize, vk::DeviceSize)> {
+		let range = binding
+			.range
+			.unwrap_or(element_count.saturating_sub(binding.offset));
+		if range == 0
+			|| binding
+				.offset
+				.checked_add(range)
+				.is_none_or(|end| end > element_count)
+		{
+			return Err(anyhow!(
+				"binding {} covers elements {}..{}, outside its buffer of {}",
+				number,
+				binding.offset,
+				binding.offset.saturating_add(range),
+				element_count
+			));
+		}
+
+		let alignment = match binding.kind {
+			BufferKind::Storage => self.limits.min_storage_buffer_offset_alignment,
+			BufferKind::Uniform => self.limits.min_uniform_buffer_offset_alignment,
+		};
+		let offset = checked_size(binding.offset, size_of::<f32>())?;
+		if offset % alignment.max(1) != 0 {
+			return Err(anyhow!(
+				"binding {} starts at byte {}, which isn't a multiple of the device's {} byte {:?} offset alignment",
+				number,
+				offset,
+				alignment,
+				binding.kind
+			));
+		}
+
+		let range = match binding.range {
+			Some(range) => checked_size(range, size_of::<f32>())?,
+			None => vk::WHOLE_SIZE as vk::DeviceSize,
+		};
+		Ok((offset, range))
+	}
+
+	/// A buffer bound to its own host-visible, host-coherent allocation.
+	unsafe fn create_host_visible_buffer(
+		&self,
+		size: vk::DeviceSize,
+		usage: vk::BufferUsageFlags,
+	) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+		let buffer_info = vk::BufferCreateInfo::builder()
+			.size(size)
+			.usage(usage)
+			.sharing_mode(vk::SharingMode::EXCLUSIVE)
+			.build();
+		let buffer = self.logical_device.create_buffer(&buffer_info, None)?;
+		match self.bind_host_visible_memory(buffer) {
+			Ok(memory) => Ok((buffer, memory)),
+			Err(e) => {
+				self.logical_device.destroy_buffer(buffer, None);
+				Err(e)
+			}
+		}
+	}
+
+	/// Allocates and binds `buffer`'s memory, freeing it again if binding fails. The
+	/// buffer is the caller's to destroy either way.
+	unsafe fn bind_host_visible_memory(&self, buffer: vk::Buffer) -> Result<vk::DeviceMemory> {
+		// the type already picked for the main allocation is host-visible, and unless
+		// it's host-cached also coherent, so only look further if that won't do
+		let requirements = self.logical_device.get_buffer_memory_requirements(buffer);
+		let memory_properties = self
+			.instance
+			.get_physical_device_memory_properties(self.physical_device);
+		let memory_type_index = if requirements.memory_type_bits & (1 << self.memory_index) != 0
+			&& self.is_host_coherent()
+		{
+			self.memory_index
+		} else {
+			get_memory_type_index_for_requirements(
+				&memory_properties,
+				&requirements,
+				vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+			)?
+		};
+		let heap_index = memory_properties.memory_types[memory_type_index as usize].heap_index;
+		get_heap_sizes(&self.instance, self.physical_device)?
+			.check(heap_index, requirements.size)?;
+
+		let memory_allocate_info = vk::MemoryAllocateInfo::builder()
+			.allocation_size(requirements.size)
+			.memory_type_index(memory_type_index)
+			.build();
+		let memory = self
+			.logical_device
+			.allocate_memory(&memory_allocate_info, None)
+			.map_err(ComputeError::Vulkan)?;
+		if let Err(e) = self.logical_device.bind_buffer_memory(buffer, memory, 0) {
+			self.logical_device.free_memory(memory, None);
+			return Err(e.into());
+		}
+
+		Ok(memory)
+	}
+
+	/// Records each pipeline as a pass, alternating between the two descriptor sets so
+	/// every pass reads what the previous one wrote. `zeroed_buffer` is cleared first so
+	/// elements a shader skips read back as zero rather than whatever was in memory.
+	/// `buffer_addresses` get pushed as constants, swapped along with the descriptor sets.
+	/// `statistics_query` is reset and wrapped around every pass. `timestamp_query`, a
+	/// pool and its size, gets a timestamp before the first pass and after each one, as
+	/// many as fit. With `pass_events`, one
+	/// per boundary between passes, each pass sets an event the next waits on instead of
+	/// a pipeline barrier separating them; they have to have been reset beforehand.
+	/// With exclusive buffers, `staged_upload` acquires the input buffer from the
+	/// transfer family before the first pass, matching the release recorded after the
+	/// copy.
+	#[allow(clippy::too_many_arguments)]
+	pub unsafe fn record_commands_to_buffer(
+		&mut self,
+		command_buffer: &vk::CommandBuffer,
+		pipelines: &[vk::Pipeline],
+		pipeline_layout: &vk::PipelineLayout,
+		descriptor_sets: &[Vec<vk::DescriptorSet>; 2],
+		indirect_buffer: Option<&vk::Buffer>,
+		zeroed_buffer: Option<&vk::Buffer>,
+		buffer_addresses: Option<[vk::DeviceAddress; 2]>,
+		statistics_query: Option<&vk::QueryPool>,
+		timestamp_query: Option<(&vk::QueryPool, u32)>,
+		pass_events: &[vk::Event],
+		staged_upload: bool,
+	) -> Result<()> {
+		// no ONE_TIME_SUBMIT, the buffer gets reset and re-recorded for the next dispatch
+		let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder().build();
+
+		self.logical_device
+			.begin_command_buffer(*command_buffer, &command_buffer_begin_info)
+			.context("couldn't begin recording the command buffer")?;
+
+		if let Some(query_pool) = statistics_query {
+			self.logical_device
+				.cmd_reset_query_pool(*command_buffer, *query_pool, 0, 1);
+			self.logical_device.cmd_begin_query(
+				*command_buffer,
+				*query_pool,
+				0,
+				vk::QueryControlFlags::empty(),
+			);
+		}
+		let write_timestamp = |index: usize| {
+			if let Some((query_pool, count)) = timestamp_query {
+				if index < count as usize {
+					self.logical_device.cmd_write_timestamp(
+						*command_buffer,
+						vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+						*query_pool,
+						index as u32,
+					);
+				}
+			}
+		};
+		if let Some((query_pool, count)) = timestamp_query {
+			self.logical_device
+				.cmd_reset_query_pool(*command_buffer, *query_pool, 0, count);
+		}
+
+		if let Some(zeroed_buffer) = zeroed_buffer {
+			self.logical_device.cmd_fill_buffer(
+				*command_buffer,
+				*zeroed_buffer,
+				0,
+				vk::WHOLE_SIZE as vk::DeviceSize,
+				0,
+			);
+			let barrier = vk::MemoryBarrier::builder()
+				.src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+				.dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+				.build();
+			self.logical_device.cmd_pipeline_barrier(
+				*command_buffer,
+				vk::PipelineStageFlags::TRANSFER,
+				vk::PipelineStageFlags::COMPUTE_SHADER,
+				vk::DependencyFlags::empty(),
+				&[barrier],
+				&[] as &[vk::BufferMemoryBarrier],
+				&[] as &[vk::ImageMemoryBarrier],
+			);
+		}
+		if let (true, Some(transfer_queue_index)) =
+			(staged_upload, self.ownership_transfer_family())
+		{
+			let acquire = self.input_ownership_transfer(
+				transfer_queue_index,
+				vk::AccessFlags::empty(),
+				vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+			);
+			// from the stage the submit waits on the upload's semaphore at
+			self.logical_device.cmd_pipeline_barrier(
+				*command_buffer,
+				vk::PipelineStageFlags::COMPUTE_SHADER,
+				vk::PipelineStageFlags::COMPUTE_SHADER,
+				vk::DependencyFlags::empty(),
+				&[] as &[vk::MemoryBarrier],
+				&[acquire],
+				&[] as &[vk::ImageMemoryBarrier],
+			);
+		}
+		// after the clear, so the first pass's time is its own
+		write_timestamp(0);
+
+		for (pass, pipeline) in pipelines.iter().enumerate() {
+			if pass > 0 {
+				let barrier = vk::MemoryBarrier::builder()
+					.src_access_mask(vk::AccessFlags::SHADER_WRITE)
+					.dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+					.build();
+				match pass_events.get(pass - 1) {
+					Some(event) => self.logical_device.cmd_wait_events(
+						*command_buffer,
+						&[*event],
+						vk::PipelineStageFlags::COMPUTE_SHADER,
+						vk::PipelineStageFlags::COMPUTE_SHADER,
+						&[barrier],
+						&[] as &[vk::BufferMemoryBarrier],
+						&[] as &[vk::ImageMemoryBarrier],
+					),
+					None => self.logical_device.cmd_pipeline_barrier(
+						*command_buffer,
+						vk::PipelineStageFlags::COMPUTE_SHADER,
+						vk::PipelineStageFlags::COMPUTE_SHADER,
+						vk::DependencyFlags::empty(),
+						&[barrier],
+						&[] as &[vk::BufferMemoryBarrier],
+						&[] as &[vk::ImageMemoryBarrier],
+					),
+				}
+			}
+
+			self.logical_device.cmd_bind_pipeline(
+				*command_buffer,
+				vk::PipelineBindPoint::COMPUTE,
+				*pipeline,
+			);
+
+			self.logical_device.cmd_bind_descriptor_sets(
+				*command_buffer,
+				vk::PipelineBindPoint::COMPUTE,
+				*pipeline_layout,
+				0,
+				&descriptor_sets[pass % 2],
+				&[],
+			);
+
+			if let Some([in_address, out_address]) = buffer_addresses {
+				let addresses = if pass % 2 == 0 {
+					[in_address, out_address]
+				} else {
+					[out_address, in_address]
+				};
+				let bytes = addresses
+					.iter()
+					.flat_map(|address| address.to_ne_bytes())
+					.collect::<Vec<_>>();
+				self.logical_device.cmd_push_constants(
+					*command_buffer,
+					*pipeline_layout,
+					vk::ShaderStageFlags::COMPUTE,
+					0,
+					&bytes,
+				);
+			}
+
+			if let Some(indirect_buffer) = indirect_buffer {
+				self.logical_device
+					.cmd_dispatch_indirect(*command_buffer, *indirect_buffer, 0);
+			} else {
+				let [x, y, z] = self.group_counts;
+				self.logical_device.cmd_dispatch(*command_buffer, x, y, z);
+			}
+			write_timestamp(pass + 1);
+
+			if let Some(event) = pass_events.get(pass) {
+				self.logical_device.cmd_set_event(
+					*command_buffer,
+					*event,
+					vk::PipelineStageFlags::COMPUTE_SHADER,
+				);
+			}
+		}
+
+		if let Some(query_pool) = statistics_query {
+			self.logical_device
+				.cmd_end_query(*command_buffer, *query_pool, 0);
+		}
+
+		self.logical_device
+			.end_command_buffer(*command_buffer)
+			.with_context(|| format!("couldn't finish recording {} pass(es)", pipelines.len()))
+	}
+
+	/// `wait_for_fences` reports a timeout as a success code, so check for it rather
+	/// than reading back whatever the shader has managed so far.
+	pub unsafe fn wait_for_done_fence(&self, timeout_ms: u64, retries: u32) -> Result<()> {
+		self.wait_for_all_fences(&[self.done_fence], timeout_ms, retries)
+	}
+
+	/// Waits for every one of `fences` in a single call, with the same timeout handling
+	/// as `wait_for_done_fence`.
+	pub unsafe fn wait_for_all_fences(
+		&self,
+		fences: &[vk::Fence],
+		timeout_ms: u64,
+		retries: u32,
+	) -> Result<()> {
+		let timeout = timeout_ms.saturating_mul(NANOS_PER_MILLI);
+		for attempt in 0..=retries {
+			match self
+				.logical_device
+				.wait_for_fences(fences, true, timeout)
+				.map_err(|e| device_error(e, "waiting for the dispatch"))?
+			{
+				vk::SuccessCode::TIMEOUT | vk::SuccessCode::NOT_READY => {
+					warn!(
+						"compute still running after {}ms (wait {} of {})",
+						timeout_ms,
+						attempt + 1,
+						retries + 1
+					);
+				}
+				_ => return Ok(()),
+			}
+		}
+		Err(anyhow!(
+			"compute did not finish within {}ms x {} waits; the shader may be stuck in an \
+			 infinite loop (raise fence_timeout_ms or fence_wait_retries if it is just slow)",
+			timeout_ms,
+			retries + 1
+		))
+	}
+
+	/// After an odd number of passes the result is in the out buffer, after an even
+	/// number it has ping-ponged back into the in buffer. With `upload_semaphore` the
+	/// shaders wait for it, while clearing the output can go ahead of the upload.
+	#[rustfmt::skip]
+	pub unsafe fn do_the_thing(&mut self, command_buffer: &vk::CommandBuffer, pass_count: usize,
+			config: &ComputeConfig, upload_semaphore: Option<vk::Semaphore>) -> Result<Vec<f32>> {
+		let queue : vk::Queue = self.logical_device
+			.get_device_queue(self.queue_index, 0);
+		let command_buffer_wrapper = &[*command_buffer];
+		let wait_semaphores = upload_semaphore.as_slice();
+		let wait_stages = wait_semaphores.iter()
+			.map(|_| vk::PipelineStageFlags::COMPUTE_SHADER)
+			.collect::<Vec<_>>();
+
+		let submit_info = &[vk::SubmitInfo::builder()
+			.wait_semaphores(wait_semaphores)
+			.wait_dst_stage_mask(&wait_stages)
+			.command_buffers(command_buffer_wrapper)
+			.build()];
+		
+		{
+			let _phase = phase!("submit");
+			self.logical_device.reset_fences(&[self.done_fence])?;
+			self.logical_device.queue_submit(queue, submit_info, self.done_fence)
+				.map_err(|e| device_error(e, "submitting work"))?;
+			self.wait_for_done_fence(config.fence_timeout_ms, config.fence_wait_retries)?;
+		}
+		
+		let _phase = phase!("readback");
+		self.read_floats(self.memory, pass_count)
+	}
+
+	/// Reads back whichever buffer in `memory` the last of `pass_count` passes wrote.
+	unsafe fn read_floats(&self, memory: BufferMemory, pass_count: usize) -> Result<Vec<f32>> {
+		let buffer_size = self.buffer_size();
+		let (memory, result_offset) = memory.location(pass_count % 2, self.buffer_stride());
+		let (mapped, range) = self.map_buffer_memory(memory, result_offset, buffer_size)?;
+		if !self.is_host_coherent() {
+			self.logical_device
+				.invalidate_mapped_memory_ranges(&[range])?;
+		}
+
+		let mut bytes: Vec<u8> = vec![0; buffer_size as usize];
+		memcpy(mapped.cast_const(), bytes.as_mut_ptr(), bytes.len());
+		self.logical_device.unmap_memory(memory);
+
+		let values = self.element_type.decode(&bytes);
+		if self.element_stride == 1 {
+			return Ok(values);
+		}
+		Ok(values
+			.into_iter()
+			.skip(self.element_offset as usize)
+			.step_by(self.element_stride as usize)
+			.collect())
+	}
+
+	/// Allocates a job's buffers, descriptor sets over them laid out like the main ones
+	/// from `descriptor_sets` and a command buffer from `command_pool`. `fence` must be
+	/// unsignalled.
+	pub unsafe fn create_batch_job(
+		&self,
+		command_pool: &vk::CommandPool,
+		descriptor_sets: &mut DescriptorSetPool,
+		layouts: &[vk::DescriptorSetLayout],
+		descriptor_set: u32,
+		extra_buffers: &[ExtraBuffer],
+		fence: vk::Fence,
+	) -> Result<BatchJob> {
+		// earlier jobs' buffers come out of the same budget
+		let heap_sizes = get_heap_sizes(&self.instance, self.physical_device)?;
+		let (memory, in_buffer, out_buffer) = create_buffer_pair(
+			&self.logical_device,
+			self.memory_index,
+			(&heap_sizes, self.memory_heap_index),
+			self.buffer_size(),
+			self.buffer_stride(),
+			self.buffer_device_address,
+			self.buffer_usage,
+			&self.buffer_queue_families(),
+		)?;
+		let (descriptor_pool, sets) = match descriptor_sets.allocate(
+			&self.logical_device,
+			&ping_pong_layouts(layouts, descriptor_set),
+		) {
+			Ok(allocated) => allocated,
+			Err(e) => {
+				self.logical_device.destroy_buffer(in_buffer, None);
+				self.logical_device.destroy_buffer(out_buffer, None);
+				for memory in memory.allocations() {
+					self.logical_device.free_memory(*memory, None);
+				}
+				return Err(e);
+			}
+		};
+		let descriptor_sets =
+			self.write_ping_pong_sets(sets, &in_buffer, &out_buffer, descriptor_set, extra_buffers);
+
+		let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::builder()
+			.command_pool(*command_pool)
+			.level(vk::CommandBufferLevel::PRIMARY)
+			.command_buffer_count(1)
+			.build();
+		let command_buffer = self
+			.logical_device
+			.allocate_command_buffers(&command_buffer_alloc_info)?
+			.remove(0);
+
+		let buffer_addresses = self
+			.buffer_device_address
+			.then(|| self.get_buffer_addresses(&in_buffer, &out_buffer));
+
+		Ok(BatchJob {
+			memory,
+			in_buffer,
+			out_buffer,
+			descriptor_pool,
+			descriptor_sets,
+			command_buffer,
+			fence,
+			buffer_addresses,
+		})
+	}
+
+	pub unsafe fn write_job_input(&self, job: &BatchJob, floats: &[f32]) -> Result<()> {
+		self.write_floats(job.memory, floats)
+	}
+
+	pub unsafe fn read_job_output(&self, job: &BatchJob, pass_count: usize) -> Result<Vec<f32>> {
+		self.read_floats(job.memory, pass_count)
+	}
+
+	/// Submits every job at once, each signalling its own fence, then waits on them all.
+	pub unsafe fn submit_batch(&self, jobs: &[BatchJob], config: &ComputeConfig) -> Result<()> {
+		let _phase = phase!("submit");
+		let queue = self.logical_device.get_device_queue(self.queue_index, 0);
+		for job in jobs {
+			let command_buffers = &[job.command_buffer];
+			let submit_info = vk::SubmitInfo::builder()
+				.command_buffers(command_buffers)
+				.build();
+			self.logical_device
+				.queue_submit(queue, &[submit_info], job.fence)
+				.map_err(|e| device_error(e, "submitting a batch"))?;
+		}
+
+		let fences = jobs.iter().map(|job| job.fence).collect::<Vec<_>>();
+		self.wait_for_all_fences(&fences, config.fence_timeout_ms, config.fence_wait_retries)
+	}
+
+	/// Frees everything but the fence, which is handed back for reuse. The descriptor
+	/// sets go back to `descriptor_sets`.
+	pub unsafe fn destroy_batch_job(
+		&self,
+		command_pool: &vk::CommandPool,
+		descriptor_sets: &mut DescriptorSetPool,
+		job: BatchJob,
+	) -> vk::Fence {
+		self.logical_device
+			.free_command_buffers(*command_pool, &[job.command_buffer]);
+		// the odd passes' sets are the even ones' but for the swapped one
+		let [even_sets, odd_sets] = &job.descriptor_sets;
+		let swapped = odd_sets
+			.iter()
+			.zip(even_sets)
+			.filter(|(odd, even)| odd != even)
+			.map(|(odd, _)| odd);
+		let sets = even_sets.iter().chain(swapped).copied().collect::<Vec<_>>();
+		descriptor_sets.free(&self.logical_device, job.descriptor_pool, &sets);
+		self.logical_device.destroy_buffer(job.in_buffer, None);
+		self.logical_device.destroy_buffer(job.out_buffer, None);
+		for memory in job.memory.allocations() {
+			self.logical_device.free_memory(*memory, None);
+		}
+		job.fence
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	pub unsafe fn destroy(
+		&mut self,
+		command_pool: vk::CommandPool,
+		descriptor_pool: vk::DescriptorPool,
+		descriptor_layouts: Vec<vk::DescriptorSetLayout>,
+		pipelines: Vec<vk::Pipeline>,
+		pipeline_layout: vk::PipelineLayout,
+		indirect: Option<(vk::Buffer, vk::DeviceMemory)>,
+		extra_buffers: Vec<ExtraBuffer>,
+	) -> Result<()> {
+		self.logical_device.destroy_command_pool(command_pool, None);
+		self.logical_device
+			.destroy_descriptor_pool(descriptor_pool, None);
+		for layout in descriptor_layouts {
+			self.logical_device
+				.destroy_descriptor_set_layout(layout, None);
+		}
+		for pipeline in pipelines {
+			self.logical_device.destroy_pipeline(pipeline, None);
+		}
+		self.logical_device
+			.destroy_pipeline_layout(pipeline_layout, None);
+		if let Some((indirect_buffer, indirect_memory)) = indirect {
+			self.logical_device.destroy_buffer(indirect_buffer, None);
+			self.logical_device.free_memory(indirect_memory, None);
+		}
+		self.destroy_extra_buffers(&extra_buffers);
+		self.destroy_resources();
+		self.logical_device.destroy_device(None);
+		if let Some(messenger) = self.debug_messenger {
+			self.instance
+				.destroy_debug_utils_messenger_ext(messenger, None);
+		}
+		self.instance.destroy_instance(None);
+		Ok(())
+	}
+}
+
+fn log_validation(validation: bool) {
+	let (validation_status, background) = if validation {
+		("ENABLED", AnsiColors::BrightBlue)
+	} else {
+		("DISABLED", AnsiColors::BrightGreen)
+	};
+	debug!(
+		"debug extensions are {}",
+		validation_status.if_supports_color(Stream::Stderr, |t| t
+			.color(AnsiColors::BrightWhite)
+			.on_color(background))
+	);
+}
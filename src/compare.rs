@@ -0,0 +1,105 @@
+use anyhow::{anyhow, Result};
+
+/// How far apart two runs' results are.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Comparison {
+	pub len: usize,
+	/// elements that aren't equal as floats, so +0 and -0 match, with NaN matching NaN
+	pub differing: usize,
+	pub max_absolute: f32,
+	/// `|a - b| / max(|a|, |b|)`, so always within `[0, 1]` for finite values
+	pub max_relative: f32,
+	/// indices of the largest absolute differences, largest first
+	pub worst: Vec<usize>,
+}
+
+/// Compares `expected` and `actual` element by element, keeping the `worst_count`
+/// indices that differ the most.
+pub fn compare(expected: &[f32], actual: &[f32], worst_count: usize) -> Result<Comparison> {
+	if expected.len() != actual.len() {
+		return Err(anyhow!(
+			"can't compare {} elements with {}",
+			expected.len(),
+			actual.len()
+		));
+	}
+
+	let mut comparison = Comparison {
+		len: expected.len(),
+		..Comparison::default()
+	};
+	let mut differences = Vec::new();
+	for (index, (&a, &b)) in expected.iter().zip(actual).enumerate() {
+		if a == b || (a.is_nan() && b.is_nan()) {
+			continue;
+		}
+		let absolute = (a - b).abs();
+		// one side NaN or infinite: as far off as it gets
+		let absolute = if absolute.is_nan() {
+			f32::INFINITY
+		} else {
+			absolute
+		};
+		let relative = absolute / a.abs().max(b.abs());
+		comparison.differing += 1;
+		comparison.max_absolute = comparison.max_absolute.max(absolute);
+		comparison.max_relative = comparison.max_relative.max(relative.min(1.0));
+		differences.push((absolute, index));
+	}
+
+	differences.sort_by(|x, y| y.0.total_cmp(&x.0).then(x.1.cmp(&y.1)));
+	comparison.worst = differences
+		.into_iter()
+		.take(worst_count)
+		.map(|(_, index)| index)
+		.collect();
+	Ok(comparison)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn nans_match_each_other() {
+		let comparison = compare(&[f32::NAN, 1.0], &[f32::NAN, 1.0], 10).unwrap();
+		assert_eq!(comparison.differing, 0);
+		assert!(comparison.worst.is_empty());
+	}
+
+	#[test]
+	fn a_nan_against_a_number_is_as_far_off_as_it_gets() {
+		let comparison = compare(&[f32::NAN, 1.0], &[2.0, 1.0], 10).unwrap();
+		assert_eq!(comparison.differing, 1);
+		assert_eq!(comparison.max_absolute, f32::INFINITY);
+		assert_eq!(comparison.max_relative, 1.0);
+		assert_eq!(comparison.worst, [0]);
+	}
+
+	#[test]
+	fn relative_differences_stay_within_one() {
+		let comparison = compare(&[f32::INFINITY, -1.0], &[1.0, 1.0], 10).unwrap();
+		assert_eq!(comparison.max_absolute, f32::INFINITY);
+		assert_eq!(comparison.max_relative, 1.0);
+
+		let comparison = compare(&[4.0], &[3.0], 10).unwrap();
+		assert_eq!(comparison.max_absolute, 1.0);
+		assert_eq!(comparison.max_relative, 0.25);
+	}
+
+	#[test]
+	fn different_lengths_are_an_error() {
+		let error = compare(&[1.0, 2.0], &[1.0], 10).unwrap_err();
+		assert_eq!(error.to_string(), "can't compare 2 elements with 1");
+	}
+
+	#[test]
+	fn worst_is_largest_first_then_by_index() {
+		let expected = [0.0, 0.0, 0.0, 0.0, 0.0];
+		let actual = [1.0, 3.0, -1.0, 3.0, 0.0];
+		let comparison = compare(&expected, &actual, 3).unwrap();
+		assert_eq!(comparison.len, 5);
+		assert_eq!(comparison.differing, 4);
+		assert_eq!(comparison.worst, [1, 3, 0]);
+	}
+}
@@ -0,0 +1,581 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use vulkanalia::prelude::v1_1::*;
+
+use crate::app::{
+	config_descriptor_bindings, App, AppBuilder, BatchJob, DescriptorBinding, DescriptorSetPool,
+	ExtraBuffer, StagedUpload,
+};
+use crate::reflect::{bound_extra_buffers, reflect_layout, reflect_shaders, ShaderLayout};
+use crate::{create_shader_module, ComputeConfig, ComputeError, PassSync, StageTimings};
+
+/// Unsignalled fences left over from earlier batches, so a dispatch loop doesn't create
+/// and destroy one per job.
+#[derive(Default)]
+pub struct FencePool {
+	free: Vec<vk::Fence>,
+}
+
+impl FencePool {
+	pub unsafe fn acquire(&mut self, device: &Device) -> Result<vk::Fence> {
+		match self.free.pop() {
+			Some(fence) => Ok(fence),
+			None => Ok(device.create_fence(&vk::FenceCreateInfo::default(), None)?),
+		}
+	}
+
+	/// `fence` must no longer be in use by the device. One that can't be reset, say
+	/// after the device was lost, is destroyed instead.
+	pub unsafe fn release(&mut self, device: &Device, fence: vk::Fence) {
+		match device.reset_fences(&[fence]) {
+			Ok(()) => self.free.push(fence),
+			Err(_) => device.destroy_fence(fence, None),
+		}
+	}
+
+	pub unsafe fn destroy(&mut self, device: &Device) {
+		for fence in self.free.drain(..) {
+			device.destroy_fence(fence, None);
+		}
+	}
+}
+
+/// The instance, device, pipelines and buffers from one setup, kept around so repeated
+/// dispatches only pay for the upload, the submit and the readback.
+pub struct ComputeContext {
+	pub app: App,
+	config: ComputeConfig,
+	in_buffer: vk::Buffer,
+	out_buffer: vk::Buffer,
+	extra_buffers: Vec<ExtraBuffer>,
+	/// the ones the shaders actually use, which go in the descriptor sets
+	bound_extra_buffers: Vec<ExtraBuffer>,
+	descriptor_bindings: Vec<DescriptorBinding>,
+	/// one per set up to the highest one bound
+	descriptor_layouts: Vec<vk::DescriptorSetLayout>,
+	descriptor_pool: vk::DescriptorPool,
+	/// for even and odd passes
+	descriptor_sets: [Vec<vk::DescriptorSet>; 2],
+	pipeline: vk::Pipeline,
+	pass_pipelines: Vec<vk::Pipeline>,
+	pipeline_layout: vk::PipelineLayout,
+	/// bytes of push constants the shaders declared at setup
+	push_constant_size: u32,
+	command_pool: vk::CommandPool,
+	command_buffer: vk::CommandBuffer,
+	indirect: Option<(vk::Buffer, vk::DeviceMemory)>,
+	buffer_addresses: Option<[vk::DeviceAddress; 2]>,
+	statistics_query: Option<vk::QueryPool>,
+	/// with `pass_timings`, and how many timestamps it holds
+	timestamp_query: Option<(vk::QueryPool, u32)>,
+	/// from the last dispatch's timestamps
+	pass_durations: Vec<Duration>,
+	/// the last dispatch's contents of every binding marked `readback`
+	binding_readbacks: Vec<(u32, Vec<f32>)>,
+	/// one per boundary between passes with `pass_sync = "event"`
+	pass_events: Vec<vk::Event>,
+	/// with `staged_upload` and a transfer queue to do it on
+	staged_upload: Option<StagedUpload>,
+	fence_pool: FencePool,
+	/// where batch jobs' descriptor sets come from
+	job_descriptor_sets: DescriptorSetPool,
+	/// the stages after the app's own, see [`ComputeContext::setup_timings`]
+	setup_timings: StageTimings,
+}
+
+impl ComputeContext {
+	pub unsafe fn new(builder: AppBuilder, config: &ComputeConfig) -> Result<Self> {
+		let reflected = match reflect_shaders(&builder, config) {
+			Ok(layout) => Some(layout),
+			Err(e) => {
+				warn!(
+					"couldn't reflect the shaders, using the configured bindings: {:#}",
+					e
+				);
+				None
+			}
+		};
+
+		let app = builder.build()?;
+		let mut context = Self {
+			app,
+			config: config.clone(),
+			in_buffer: vk::Buffer::null(),
+			out_buffer: vk::Buffer::null(),
+			extra_buffers: Vec::new(),
+			bound_extra_buffers: Vec::new(),
+			descriptor_bindings: Vec::new(),
+			descriptor_layouts: Vec::new(),
+			descriptor_pool: vk::DescriptorPool::null(),
+			descriptor_sets: [Vec::new(), Vec::new()],
+			pipeline: vk::Pipeline::null(),
+			pass_pipelines: Vec::new(),
+			pipeline_layout: vk::PipelineLayout::null(),
+			push_constant_size: 0,
+			command_pool: vk::CommandPool::null(),
+			command_buffer: vk::CommandBuffer::null(),
+			indirect: None,
+			buffer_addresses: None,
+			statistics_query: None,
+			timestamp_query: None,
+			pass_durations: Vec::new(),
+			binding_readbacks: Vec::new(),
+			pass_events: Vec::new(),
+			staged_upload: None,
+			fence_pool: FencePool::default(),
+			job_descriptor_sets: DescriptorSetPool::default(),
+			setup_timings: StageTimings::start(),
+		};
+		// destroying the null handles of whatever wasn't created yet does nothing
+		match context.set_up(reflected) {
+			Ok(()) => Ok(context),
+			Err(e) => {
+				let _ = context.destroy();
+				Err(e)
+			}
+		}
+	}
+
+	/// [`ComputeContext::new`], but on running out of device memory halving the element
+	/// count and setting up again until the buffers fit, for
+	/// [`ComputeContext::dispatch_chunked`] to run inputs of the original size through.
+	pub unsafe fn new_chunked(builder: AppBuilder, config: &ComputeConfig) -> Result<Self> {
+		let mut element_count = builder.get_element_count();
+		loop {
+			let e = match Self::new(builder.clone().element_count(element_count), config) {
+				Err(e) if ComputeError::is_out_of_memory(&e) => e,
+				outcome => return outcome,
+			};
+			if builder.get_dimensions().is_some() {
+				return Err(
+					e.context("a dispatch over fixed dimensions can't be split into chunks")
+				);
+			}
+			if element_count == 1 {
+				return Err(e);
+			}
+			warn!(
+				"{} elements don't fit ({:#}), trying chunks of {}",
+				element_count,
+				e,
+				element_count / 2
+			);
+			element_count /= 2;
+		}
+	}
+
+	/// Everything after the [`App`], each stored as soon as it exists so a failure
+	/// partway can still be torn down by [`ComputeContext::destroy`].
+	unsafe fn set_up(&mut self, reflected: Option<ShaderLayout>) -> Result<()> {
+		let config = &self.config;
+		let app = &mut self.app;
+
+		if let Some(layout) = &reflected {
+			let limit = app.limits.max_compute_shared_memory_size as u64;
+			if layout.shared_memory_size > limit {
+				return Err(anyhow!(
+					"shaders declare at least {} bytes of shared memory, over the device's {}",
+					layout.shared_memory_size,
+					limit
+				));
+			}
+		}
+
+		// the spec has compute families run transfer commands regardless, but not every
+		// portability driver lives up to that
+		if config.zero_output && !app.compute_queue_flags().contains(vk::QueueFlags::TRANSFER) {
+			warn!(
+				"compute queue family {} doesn't advertise TRANSFER, which clearing the \
+				 output relies on; set zero_output = false if that fails",
+				app.queue_index
+			);
+		}
+
+		self.extra_buffers = app.create_extra_buffers(&config.bindings, config.descriptor_set)?;
+		self.setup_timings.end("extra buffers");
+		(
+			self.descriptor_bindings,
+			self.bound_extra_buffers,
+			self.push_constant_size,
+		) = match reflected {
+			Some(layout) => {
+				let bound = bound_extra_buffers(
+					&layout.bindings,
+					&self.extra_buffers,
+					config.descriptor_set,
+				)?;
+				(layout.bindings, bound, layout.push_constant_size)
+			}
+			None => (
+				config_descriptor_bindings(&config.bindings, config.descriptor_set),
+				self.extra_buffers.clone(),
+				0,
+			),
+		};
+
+		(self.in_buffer, self.out_buffer, self.descriptor_layouts) =
+			app.bind_buffer_layout(&self.descriptor_bindings)?;
+		self.job_descriptor_sets = DescriptorSetPool::new(&self.descriptor_bindings);
+		self.setup_timings.end("descriptor layout");
+
+		(self.pipeline, self.pipeline_layout) =
+			app.create_pipeine_with_layout(&self.descriptor_layouts, self.push_constant_size)?;
+		self.pass_pipelines = app.create_pass_pipelines(&self.pipeline_layout, &config.passes)?;
+		self.setup_timings.end("pipelines");
+
+		(self.command_pool, self.command_buffer) = app.create_command_pool_and_buffer()?;
+		self.setup_timings.end("command buffer");
+
+		(self.descriptor_pool, self.descriptor_sets) = app.create_descriptor_pool_and_set(
+			&self.in_buffer,
+			&self.out_buffer,
+			&self.descriptor_layouts,
+			config.descriptor_set,
+			&self.descriptor_bindings,
+			&self.bound_extra_buffers,
+		)?;
+		self.setup_timings.end("descriptor sets");
+
+		if config.indirect_dispatch {
+			self.indirect = Some(app.create_indirect_buffer()?);
+			self.setup_timings.end("indirect buffer");
+		}
+
+		if config.pipeline_statistics {
+			self.statistics_query = app.create_statistics_query_pool()?;
+			self.setup_timings.end("statistics query");
+		}
+
+		if config.pass_timings {
+			// one before the first pass and one after each
+			let count = config.passes.len().max(1) as u32 + 1;
+			self.timestamp_query = app
+				.create_timestamp_query_pool(count)?
+				.map(|query_pool| (query_pool, count));
+			self.setup_timings.end("timestamp query");
+		}
+
+		if config.pass_sync == PassSync::Event && config.passes.len() > 1 {
+			self.pass_events = app.create_pass_events(config.passes.len() - 1)?;
+			self.setup_timings.end("pass events");
+		}
+
+		if config.staged_upload {
+			self.staged_upload = app.create_staged_upload()?;
+			self.setup_timings.end("staging buffer");
+		}
+
+		self.buffer_addresses = app
+			.buffer_device_address
+			.then(|| app.get_buffer_addresses(&self.in_buffer, &self.out_buffer));
+
+		Ok(())
+	}
+
+	/// Every setup stage so far and how long it took, the app's first.
+	pub fn setup_timings(&self) -> impl Iterator<Item = &(&'static str, Duration)> {
+		self.app
+			.setup_timings
+			.stages
+			.iter()
+			.chain(&self.setup_timings.stages)
+	}
+
+	/// Swaps the builder's shader for `spirv`, keeping everything else. It has to fit the
+	/// layout set up for the old one, which is checked when it can be reflected.
+	pub unsafe fn replace_shader(&mut self, spirv: &[u8]) -> Result<()> {
+		if !self.pass_pipelines.is_empty() {
+			return Err(anyhow!("the configured passes don't use the main shader"));
+		}
+		match reflect_layout(spirv) {
+			Ok(layout) => {
+				if let Some(binding) = layout
+					.bindings
+					.iter()
+					.find(|binding| !self.descriptor_bindings.contains(binding))
+				{
+					return Err(anyhow!(
+						"the shader's binding {} of set {} wasn't in the layout at setup",
+						binding.binding,
+						binding.set
+					));
+				}
+				if layout.push_constant_size > self.push_constant_size {
+					return Err(anyhow!(
+						"the shader declares {} bytes of push constants, {} at setup",
+						layout.push_constant_size,
+						self.push_constant_size
+					));
+				}
+			}
+			Err(e) => warn!("couldn't reflect the shader, assuming it fits: {:#}", e),
+		}
+
+		let shader = create_shader_module(&self.app.logical_device, spirv)?;
+		let pipeline = match self.app.create_pipeline(&self.pipeline_layout, shader, &[]) {
+			Ok(pipeline) => pipeline,
+			Err(e) => {
+				self.app.logical_device.destroy_shader_module(shader, None);
+				return Err(e);
+			}
+		};
+		// every dispatch has been waited for, so the old ones are idle
+		let device = &self.app.logical_device;
+		device.destroy_pipeline(self.pipeline, None);
+		device.destroy_shader_module(self.app.compute_shader, None);
+		self.pipeline = pipeline;
+		self.app.compute_shader = shader;
+		Ok(())
+	}
+
+	/// How long each pass of the last dispatch took on the device, with `pass_timings`
+	/// and a queue that can write timestamps; empty otherwise.
+	pub fn pass_durations(&self) -> &[Duration] {
+		&self.pass_durations
+	}
+
+	/// Each binding marked `readback` and what was in it after the last dispatch, in
+	/// binding order. Ones no shader uses aren't bound, so aren't read either.
+	pub fn binding_readbacks(&self) -> &[(u32, Vec<f32>)] {
+		&self.binding_readbacks
+	}
+
+	/// The configured passes, or just the builder's shader when there are none.
+	fn dispatch_pipelines(&self) -> Vec<vk::Pipeline> {
+		if self.pass_pipelines.is_empty() {
+			vec![self.pipeline]
+		} else {
+			self.pass_pipelines.clone()
+		}
+	}
+
+	/// Uploads `input`, runs every pass over it and reads back the result. A staged
+	/// upload is submitted once the dispatch is recorded, so a failed recording can't
+	/// leave its semaphore signalled with nothing to wait on it, and the dispatch waits
+	/// on it on the device.
+	pub unsafe fn dispatch(&mut self, input: &[f32]) -> Result<Vec<f32>> {
+		let pipelines = self.dispatch_pipelines();
+		self.app.reset_command_buffer(&self.command_buffer)?;
+		self.app.reset_pass_events(&self.pass_events)?;
+		self.app.record_commands_to_buffer(
+			&self.command_buffer,
+			&pipelines,
+			&self.pipeline_layout,
+			&self.descriptor_sets,
+			self.indirect.as_ref().map(|(buffer, _)| buffer),
+			self.config.zero_output.then_some(&self.out_buffer),
+			self.buffer_addresses,
+			self.statistics_query.as_ref(),
+			self.timestamp_query
+				.as_ref()
+				.map(|(query_pool, count)| (query_pool, *count)),
+			&self.pass_events,
+			self.staged_upload.is_some(),
+		)?;
+
+		match &self.staged_upload {
+			Some(staged) => self.app.write_staged_input(staged, input)?,
+			None => self.app.write_input(input)?,
+		}
+
+		let results = self.app.do_the_thing(
+			&self.command_buffer,
+			pipelines.len(),
+			&self.config,
+			self.staged_upload.map(|staged| staged.semaphore),
+		)?;
+
+		if let Some(query_pool) = &self.statistics_query {
+			let invocations = self.app.get_invocation_count(query_pool)?;
+			let expected = self.app.expected_invocations(pipelines.len());
+			if invocations == expected {
+				info!("compute shader ran {} invocations", invocations);
+			} else {
+				warn!(
+					"compute shader ran {} invocations, expected {}",
+					invocations, expected
+				);
+			}
+		}
+
+		if let Some((query_pool, count)) = &self.timestamp_query {
+			self.pass_durations = self.app.get_timestamp_durations(query_pool, *count)?;
+			let total = self.pass_durations.iter().sum::<Duration>();
+			for (pass, duration) in self.pass_durations.iter().enumerate() {
+				info!(
+					"pass {} took {:.3?} on the device ({:.1}%)",
+					pass,
+					duration,
+					100.0 * duration.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE)
+				);
+			}
+		}
+
+		let _phase = phase!("binding readback");
+		self.binding_readbacks = self
+			.bound_extra_buffers
+			.iter()
+			.filter(|extra| extra.readback)
+			.map(|extra| Ok((extra.binding, self.app.read_extra_buffer(extra)?)))
+			.collect::<Result<_>>()?;
+
+		Ok(results)
+	}
+
+	/// [`ComputeContext::dispatch`] for an input of any length, split into chunks of the
+	/// context's element count, the last padded with zeros, one after another. The
+	/// results are joined back up, so this only suits shaders whose elements don't read
+	/// each other.
+	pub unsafe fn dispatch_chunked(&mut self, input: &[f32]) -> Result<Vec<f32>> {
+		let chunk_size = self.app.element_count;
+		if input.len() == chunk_size {
+			return self.dispatch(input);
+		}
+
+		let mut results = Vec::with_capacity(input.len());
+		let mut padded = Vec::new();
+		for chunk in input.chunks(chunk_size) {
+			let chunk = if chunk.len() < chunk_size {
+				padded.clear();
+				padded.extend_from_slice(chunk);
+				padded.resize(chunk_size, 0.0);
+				&padded
+			} else {
+				chunk
+			};
+			results.extend(self.dispatch(chunk)?);
+		}
+		results.truncate(input.len());
+		Ok(results)
+	}
+
+	/// Runs every input as its own job, all submitted together so the device can overlap
+	/// them, and returns the results in input order. Each job gets separate buffers, so
+	/// this costs an allocation per input on top of the context's own.
+	pub unsafe fn dispatch_batch(&mut self, inputs: &[Vec<f32>]) -> Result<Vec<Vec<f32>>> {
+		let mut jobs = Vec::with_capacity(inputs.len());
+		let outcome = self.run_batch(inputs, &mut jobs);
+		// the jobs are only idle once their fences have signalled, or the device is lost
+		if outcome.is_err() {
+			let _ = self.app.logical_device.device_wait_idle();
+		}
+		for job in jobs {
+			let fence =
+				self.app
+					.destroy_batch_job(&self.command_pool, &mut self.job_descriptor_sets, job);
+			self.fence_pool.release(&self.app.logical_device, fence);
+		}
+		outcome
+	}
+
+	unsafe fn run_batch(
+		&mut self,
+		inputs: &[Vec<f32>],
+		jobs: &mut Vec<BatchJob>,
+	) -> Result<Vec<Vec<f32>>> {
+		let pipelines = self.dispatch_pipelines();
+		// each job has a set per layout and the swapped one
+		let sets_per_job = self.descriptor_layouts.len() as u32 + 1;
+		self.job_descriptor_sets
+			.reserve(&self.app.logical_device, sets_per_job * inputs.len() as u32)?;
+		for input in inputs {
+			let fence = self.fence_pool.acquire(&self.app.logical_device)?;
+			let job = match self.app.create_batch_job(
+				&self.command_pool,
+				&mut self.job_descriptor_sets,
+				&self.descriptor_layouts,
+				self.config.descriptor_set,
+				&self.bound_extra_buffers,
+				fence,
+			) {
+				Ok(job) => job,
+				Err(e) => {
+					self.fence_pool.release(&self.app.logical_device, fence);
+					return Err(e);
+				}
+			};
+			jobs.push(job.clone());
+
+			self.app.write_job_input(&job, input)?;
+			self.app.record_commands_to_buffer(
+				&job.command_buffer,
+				&pipelines,
+				&self.pipeline_layout,
+				&job.descriptor_sets,
+				self.indirect.as_ref().map(|(buffer, _)| buffer),
+				self.config.zero_output.then_some(&job.out_buffer),
+				job.buffer_addresses,
+				None,
+				None,
+				&[],
+				false,
+			)?;
+		}
+
+		self.app.submit_batch(jobs, &self.config)?;
+
+		let _phase = phase!("readback");
+		jobs.iter()
+			.map(|job| self.app.read_job_output(job, pipelines.len()))
+			.collect()
+	}
+
+	/// Safe to call after a failed dispatch, even one that lost the device.
+	pub unsafe fn destroy(mut self) -> Result<()> {
+		if self.config.wait_idle_on_destroy {
+			// a lost device fails this too, and then there's nothing left running anyway
+			if let Err(e) = self.app.logical_device.device_wait_idle() {
+				warn!(
+					"couldn't wait for the device to go idle before teardown ({})",
+					e
+				);
+			}
+		}
+		self.fence_pool.destroy(&self.app.logical_device);
+		self.job_descriptor_sets.destroy(&self.app.logical_device);
+		if let Some(query_pool) = self.statistics_query {
+			self.app.logical_device.destroy_query_pool(query_pool, None);
+		}
+		if let Some((query_pool, _)) = self.timestamp_query {
+			self.app.logical_device.destroy_query_pool(query_pool, None);
+		}
+		self.app.destroy_pass_events(&self.pass_events);
+		if let Some(staged) = &self.staged_upload {
+			self.app.destroy_staged_upload(staged);
+		}
+		self.app.destroy(
+			self.command_pool,
+			self.descriptor_pool,
+			self.descriptor_layouts,
+			[vec![self.pipeline], self.pass_pipelines].concat(),
+			self.pipeline_layout,
+			self.indirect,
+			self.extra_buffers,
+		)
+	}
+}
+
+/// Sets up, dispatches once and tears down again, for when there's only the one input.
+/// With `chunk_on_oom` an input too big for the device goes through in chunks.
+pub unsafe fn run_compute(
+	builder: AppBuilder,
+	config: &ComputeConfig,
+	input: &[f32],
+) -> Result<Vec<f32>> {
+	if config.chunk_on_oom {
+		let mut context = ComputeContext::new_chunked(builder, config)?;
+		let outcome = context.dispatch_chunked(input);
+		let destroyed = context.destroy();
+		let results = outcome?;
+		destroyed?;
+		return Ok(results);
+	}
+	let mut context = ComputeContext::new(builder, config)?;
+	let outcome = context.dispatch(input);
+	let destroyed = context.destroy();
+	let results = outcome?;
+	destroyed?;
+	Ok(results)
+}
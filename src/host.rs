@@ -0,0 +1,66 @@
+use std::fmt;
+
+use anyhow::Result;
+use log::warn;
+
+use crate::app::AppBuilder;
+use crate::context::ComputeContext;
+use crate::{ComputeConfig, ElementType};
+
+/// What a computation ended up running on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+	Vulkan,
+	Host,
+}
+
+impl fmt::Display for Backend {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Backend::Vulkan => write!(f, "Vulkan"),
+			Backend::Host => write!(f, "host"),
+		}
+	}
+}
+
+/// What the bundled shader, `src/shader.comp`, does to each element.
+pub fn doubled(value: f32) -> f32 {
+	2.0 * value
+}
+
+/// Applies `kernel` to every element, rounded through `element_type` on the way in and
+/// out like the buffers would be.
+pub fn run_on_host(
+	input: &[f32],
+	element_type: ElementType,
+	kernel: impl Fn(f32) -> f32,
+) -> Vec<f32> {
+	let output = element_type
+		.decode(&element_type.encode(input))
+		.into_iter()
+		.map(kernel)
+		.collect::<Vec<_>>();
+	element_type.decode(&element_type.encode(&output))
+}
+
+/// Like [`run_compute`](crate::context::run_compute), but if setup fails, say with no
+/// Vulkan driver at all, `kernel` runs on the host instead, standing in for every pass.
+/// Failures once the device is set up are still errors.
+pub unsafe fn run_compute_or_host(
+	builder: AppBuilder,
+	config: &ComputeConfig,
+	input: &[f32],
+	kernel: impl Fn(f32) -> f32,
+) -> Result<(Vec<f32>, Backend)> {
+	let mut context = match ComputeContext::new(builder, config) {
+		Ok(context) => context,
+		Err(e) => {
+			warn!("couldn't set up Vulkan, running on the host: {:#}", e);
+			let results = run_on_host(input, config.element_type, kernel);
+			return Ok((results, Backend::Host));
+		}
+	};
+	let outcome = context.dispatch(input);
+	context.destroy()?;
+	Ok((outcome?, Backend::Vulkan))
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+
+use anyhow::Result;
+use serde::Serialize;
+use vulkanalia::prelude::v1_1::*;
+
+use crate::{
+	get_heap_budgets, get_shader_clock_features, has_compute_queue, has_device_extension,
+	has_portability_subset_extension,
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceLimits {
+	pub max_compute_work_group_size: [u32; 3],
+	pub max_compute_work_group_count: [u32; 3],
+	pub max_compute_work_group_invocations: u32,
+	pub max_compute_shared_memory_size: u32,
+	pub min_storage_buffer_offset_alignment: vk::DeviceSize,
+	pub min_uniform_buffer_offset_alignment: vk::DeviceSize,
+	pub non_coherent_atom_size: vk::DeviceSize,
+	pub max_bound_descriptor_sets: u32,
+	/// nanoseconds per timestamp tick
+	pub timestamp_period: f32,
+}
+
+impl From<&vk::PhysicalDeviceLimits> for DeviceLimits {
+	fn from(limits: &vk::PhysicalDeviceLimits) -> Self {
+		Self {
+			max_compute_work_group_size: limits.max_compute_work_group_size,
+			max_compute_work_group_count: limits.max_compute_work_group_count,
+			max_compute_work_group_invocations: limits.max_compute_work_group_invocations,
+			max_compute_shared_memory_size: limits.max_compute_shared_memory_size,
+			min_storage_buffer_offset_alignment: limits.min_storage_buffer_offset_alignment,
+			min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment,
+			non_coherent_atom_size: limits.non_coherent_atom_size,
+			max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+			timestamp_period: limits.timestamp_period,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ComputeFeatures {
+	pub shader_float64: bool,
+	pub shader_int64: bool,
+	pub shader_int16: bool,
+	pub shader_float16: bool,
+	pub shader_int8: bool,
+	pub robust_buffer_access: bool,
+	pub pipeline_statistics_query: bool,
+	/// `VK_KHR_shader_clock`'s, for `clockARB()` and `clockRealtimeEXT()`
+	pub shader_subgroup_clock: bool,
+	pub shader_device_clock: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SubgroupInfo {
+	pub subgroup_size: u32,
+	pub supported_stages: Vec<String>,
+	pub supported_operations: Vec<String>,
+	pub quad_operations_in_all_stages: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MemoryHeapInfo {
+	pub size: vk::DeviceSize,
+	pub flags: Vec<String>,
+	/// this process's share of the heap, with `VK_EXT_memory_budget`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub budget: Option<vk::DeviceSize>,
+	/// how much of the budget is in use, with `VK_EXT_memory_budget`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub usage: Option<vk::DeviceSize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QueueFamilyInfo {
+	pub queue_count: u32,
+	pub flags: Vec<String>,
+	/// can run copies and fills: it says `TRANSFER`, or it's a compute or graphics
+	/// family, which the spec has do transfers without saying so
+	pub transfer: bool,
+	pub timestamp_valid_bits: u32,
+}
+
+/// What `list` shows for every device: who it is, its heaps and its queue families.
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceInfo {
+	pub name: String,
+	pub vendor_id: u32,
+	pub device_id: u32,
+	pub device_type: String,
+	pub api_version: String,
+	pub driver_version: u32,
+	pub has_compute_queue: bool,
+	/// only implements the portability subset, as MoltenVK does
+	pub portability_subset: bool,
+	pub memory_heaps: Vec<MemoryHeapInfo>,
+	pub queue_families: Vec<QueueFamilyInfo>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceProfile {
+	#[serde(flatten)]
+	pub device: DeviceInfo,
+	pub limits: DeviceLimits,
+	pub features: ComputeFeatures,
+	/// absent on devices that only report Vulkan 1.0
+	pub subgroup: Option<SubgroupInfo>,
+	/// absent on devices implementing all of Vulkan
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub portability: Option<PortabilitySubsetInfo>,
+}
+
+/// What a portability implementation leaves out, from `VK_KHR_portability_subset`.
+#[derive(Clone, Debug, Serialize)]
+pub struct PortabilitySubsetInfo {
+	pub min_vertex_input_binding_stride_alignment: u32,
+	/// the subset's features the device doesn't have
+	pub unsupported: Vec<String>,
+}
+
+/// `"VERTEX | COMPUTE"`-style bitflags debug output, split into one name per flag.
+fn flag_names(flags: impl fmt::Debug) -> Vec<String> {
+	format!("{:?}", flags)
+		.split(" | ")
+		.filter(|name| *name != "(empty)")
+		.map(String::from)
+		.collect()
+}
+
+pub unsafe fn get_subgroup_info(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Option<SubgroupInfo> {
+	let api_version = instance
+		.get_physical_device_properties(physical_device)
+		.api_version;
+	if api_version < vk::make_version(1, 1, 0) {
+		return None;
+	}
+
+	let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+	let mut properties = vk::PhysicalDeviceProperties2::builder()
+		.push_next(&mut subgroup)
+		.build();
+	instance.get_physical_device_properties2(physical_device, &mut properties);
+
+	Some(SubgroupInfo {
+		subgroup_size: subgroup.subgroup_size,
+		supported_stages: flag_names(subgroup.supported_stages),
+		supported_operations: flag_names(subgroup.supported_operations),
+		quad_operations_in_all_stages: subgroup.quad_operations_in_all_stages == vk::TRUE,
+	})
+}
+
+pub unsafe fn get_portability_subset_info(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Result<Option<PortabilitySubsetInfo>> {
+	if !has_portability_subset_extension(instance, physical_device)? {
+		return Ok(None);
+	}
+
+	let mut subset_features = vk::PhysicalDevicePortabilitySubsetFeaturesKHR::default();
+	let mut features = vk::PhysicalDeviceFeatures2::builder()
+		.push_next(&mut subset_features)
+		.build();
+	instance.get_physical_device_features2(physical_device, &mut features);
+	let mut subset_properties = vk::PhysicalDevicePortabilitySubsetPropertiesKHR::default();
+	let mut properties = vk::PhysicalDeviceProperties2::builder()
+		.push_next(&mut subset_properties)
+		.build();
+	instance.get_physical_device_properties2(physical_device, &mut properties);
+
+	let f = &subset_features;
+	let unsupported = [
+		(
+			"constant_alpha_color_blend_factors",
+			f.constant_alpha_color_blend_factors,
+		),
+		("events", f.events),
+		(
+			"image_view_format_reinterpretation",
+			f.image_view_format_reinterpretation,
+		),
+		("image_view_format_swizzle", f.image_view_format_swizzle),
+		("image_view_2d_on_3d_image", f.image_view_2d_on_3d_image),
+		("multisample_array_image", f.multisample_array_image),
+		("mutable_comparison_samplers", f.mutable_comparison_samplers),
+		("point_polygons", f.point_polygons),
+		("sampler_mip_lod_bias", f.sampler_mip_lod_bias),
+		("separate_stencil_mask_ref", f.separate_stencil_mask_ref),
+		(
+			"shader_sample_rate_interpolation_functions",
+			f.shader_sample_rate_interpolation_functions,
+		),
+		("tessellation_isolines", f.tessellation_isolines),
+		("tessellation_point_mode", f.tessellation_point_mode),
+		("triangle_fans", f.triangle_fans),
+		(
+			"vertex_attribute_access_beyond_stride",
+			f.vertex_attribute_access_beyond_stride,
+		),
+	]
+	.into_iter()
+	.filter(|(_, supported)| *supported != vk::TRUE)
+	.map(|(name, _)| name.to_string())
+	.collect();
+
+	Ok(Some(PortabilitySubsetInfo {
+		min_vertex_input_binding_stride_alignment: subset_properties
+			.min_vertex_input_binding_stride_alignment,
+		unsupported,
+	}))
+}
+
+pub unsafe fn get_device_info(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> DeviceInfo {
+	let props = instance.get_physical_device_properties(physical_device);
+	let memory = instance.get_physical_device_memory_properties(physical_device);
+	let queue_families = instance.get_physical_device_queue_family_properties(physical_device);
+	let budgets = get_heap_budgets(instance, physical_device).ok().flatten();
+
+	DeviceInfo {
+		name: props.device_name.to_string(),
+		vendor_id: props.vendor_id,
+		device_id: props.device_id,
+		device_type: format!("{:?}", props.device_type),
+		api_version: format!(
+			"{}.{}.{}",
+			vk::version_major(props.api_version),
+			vk::version_minor(props.api_version),
+			vk::version_patch(props.api_version)
+		),
+		driver_version: props.driver_version,
+		has_compute_queue: has_compute_queue(instance, physical_device),
+		portability_subset: has_portability_subset_extension(instance, physical_device)
+			.unwrap_or(false),
+		memory_heaps: memory.memory_heaps[..memory.memory_heap_count as usize]
+			.iter()
+			.enumerate()
+			.map(|(i, heap)| {
+				let budget = budgets.as_ref().map(|budgets| budgets[i]);
+				MemoryHeapInfo {
+					size: heap.size,
+					flags: flag_names(heap.flags),
+					budget: budget.map(|b| b.budget),
+					usage: budget.map(|b| b.usage),
+				}
+			})
+			.collect(),
+		queue_families: queue_families
+			.iter()
+			.map(|family| QueueFamilyInfo {
+				queue_count: family.queue_count,
+				flags: flag_names(family.queue_flags),
+				transfer: family.queue_flags.intersects(
+					vk::QueueFlags::TRANSFER | vk::QueueFlags::COMPUTE | vk::QueueFlags::GRAPHICS,
+				),
+				timestamp_valid_bits: family.timestamp_valid_bits,
+			})
+			.collect(),
+	}
+}
+
+pub unsafe fn get_device_profile(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Result<DeviceProfile> {
+	let props = instance.get_physical_device_properties(physical_device);
+
+	let has_float16_int8 = has_device_extension(
+		instance,
+		physical_device,
+		&vk::KHR_SHADER_FLOAT16_INT8_EXTENSION.name,
+	)?;
+	let mut float16_int8 = vk::PhysicalDeviceShaderFloat16Int8Features::default();
+	let mut features = vk::PhysicalDeviceFeatures2::builder();
+	if has_float16_int8 {
+		features = features.push_next(&mut float16_int8);
+	}
+	let mut features = features.build();
+	instance.get_physical_device_features2(physical_device, &mut features);
+	let core = features.features;
+	let shader_clock = get_shader_clock_features(instance, physical_device)?;
+
+	Ok(DeviceProfile {
+		device: get_device_info(instance, physical_device),
+		limits: DeviceLimits::from(&props.limits),
+		features: ComputeFeatures {
+			shader_float64: core.shader_float64 == vk::TRUE,
+			shader_int64: core.shader_int64 == vk::TRUE,
+			shader_int16: core.shader_int16 == vk::TRUE,
+			shader_float16: float16_int8.shader_float16 == vk::TRUE,
+			shader_int8: float16_int8.shader_int8 == vk::TRUE,
+			robust_buffer_access: core.robust_buffer_access == vk::TRUE,
+			pipeline_statistics_query: core.pipeline_statistics_query == vk::TRUE,
+			shader_subgroup_clock: shader_clock.shader_subgroup_clock == vk::TRUE,
+			shader_device_clock: shader_clock.shader_device_clock == vk::TRUE,
+		},
+		subgroup: get_subgroup_info(instance, physical_device),
+		portability: get_portability_subset_info(instance, physical_device)?,
+	})
+}
@@ -0,0 +1,239 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::Deserialize;
+
+use crate::progress::Progress;
+use crate::{checked_size, numbered_path};
+
+/// Where the input buffer's contents come from, e.g. `input_pattern = "ramp"`,
+/// `input_pattern = { constant = 1.5 }`, `input_pattern = { random = { seed = 7 } }`
+/// or `input_pattern = { from_file = "input.bin" }`.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InputPattern {
+	/// `0.0, 0.5, 1.0, ...`
+	#[default]
+	Ramp,
+	Constant(f32),
+	/// uniform in `[0, 1)`. A given seed gives the same values on every platform and
+	/// build, since ChaCha is defined byte for byte; without one a seed is taken from
+	/// the clock, see [`InputPattern::settle_seed`]
+	Random {
+		#[serde(default)]
+		seed: Option<u64>,
+	},
+	/// raw little-endian `f32`s, exactly one per element
+	FromFile(PathBuf),
+}
+
+impl InputPattern {
+	/// Pins down a random pattern's seed: `seed` if given, else the configured one, else
+	/// one from the clock. Returns it so it can be shown and the run repeated, or `None`
+	/// for patterns that aren't random.
+	pub fn settle_seed(&mut self, seed: Option<u64>) -> Option<u64> {
+		match self {
+			InputPattern::Random { seed: configured } => {
+				let settled = seed.or(*configured).unwrap_or_else(clock_seed);
+				*configured = Some(settled);
+				Some(settled)
+			}
+			_ => None,
+		}
+	}
+
+	/// The input for the `index`th of several runs: random seeds count up from the
+	/// configured one and files are numbered like [`numbered_path`]. Ramps and
+	/// constants come out the same every time.
+	pub fn nth(&self, index: usize) -> InputPattern {
+		match self {
+			InputPattern::Random { seed } => InputPattern::Random {
+				seed: seed.map(|seed| seed.wrapping_add(index as u64)),
+			},
+			InputPattern::FromFile(path) => InputPattern::FromFile(numbered_path(path, index)),
+			pattern => pattern.clone(),
+		}
+	}
+}
+
+pub fn generate_input(pattern: &InputPattern, element_count: usize) -> Result<Vec<f32>> {
+	let progress = Progress::new("generating input", element_count);
+	let input = generate(pattern, element_count, &progress);
+	progress.finish();
+	input
+}
+
+/// `0.0, 0.5, 1.0, ...`, `element_count` of them, as [`InputPattern::Ramp`] has it.
+pub fn generate_ramp(element_count: usize) -> Vec<f32> {
+	let progress = Progress::new("generating input", element_count);
+	let ramp = ramp(element_count, &progress);
+	progress.finish();
+	ramp
+}
+
+fn ramp(element_count: usize, progress: &Progress) -> Vec<f32> {
+	(0..element_count)
+		.map(|i| {
+			progress.at(i);
+			(i as f32) * 0.5
+		})
+		.collect()
+}
+
+fn generate(pattern: &InputPattern, element_count: usize, progress: &Progress) -> Result<Vec<f32>> {
+	match pattern {
+		InputPattern::Ramp => Ok(ramp(element_count, progress)),
+		InputPattern::Constant(value) => Ok(vec![*value; element_count]),
+		InputPattern::Random { seed } => {
+			let mut rng = ChaCha12Rng::seed_from_u64(seed.unwrap_or_else(clock_seed));
+			Ok((0..element_count)
+				.map(|i| {
+					progress.at(i);
+					rng.gen::<f32>()
+				})
+				.collect())
+		}
+		InputPattern::FromFile(path) => {
+			let bytes = read_file(path)?;
+			let expected = checked_size(element_count, std::mem::size_of::<f32>())?;
+			if bytes.len() as u64 != expected {
+				return Err(anyhow!(
+					"input file {} is {} bytes, expected {} ({} elements)",
+					path.display(),
+					bytes.len(),
+					expected,
+					element_count
+				));
+			}
+			Ok(decode_floats(&bytes, progress))
+		}
+	}
+}
+
+/// Raw little-endian `f32`s, however many the file holds, as `from_file` inputs and
+/// `--output-format raw` results are written.
+pub fn read_raw_floats(path: &Path) -> Result<Vec<f32>> {
+	let bytes = read_file(path)?;
+	if bytes.len() % std::mem::size_of::<f32>() != 0 {
+		return Err(anyhow!(
+			"{} is {} bytes, not a whole number of f32s",
+			path.display(),
+			bytes.len()
+		));
+	}
+	let progress = Progress::new("reading floats", bytes.len() / std::mem::size_of::<f32>());
+	let floats = decode_floats(&bytes, &progress);
+	progress.finish();
+	Ok(floats)
+}
+
+/// Nanoseconds since the epoch, different enough from one run to the next.
+fn clock_seed() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |since| since.as_nanos() as u64)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+	fs::read(path).map_err(|e| anyhow!("couldn't read {}: {}", path.display(), e))
+}
+
+fn decode_floats(bytes: &[u8], progress: &Progress) -> Vec<f32> {
+	bytes
+		.chunks_exact(std::mem::size_of::<f32>())
+		.enumerate()
+		.map(|(i, chunk)| {
+			progress.at(i);
+			f32::from_le_bytes(chunk.try_into().unwrap())
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn settle_seed_prefers_the_given_seed_then_the_configured_one() {
+		let mut pattern = InputPattern::Random { seed: Some(3) };
+		assert_eq!(pattern.settle_seed(Some(9)), Some(9));
+		assert_eq!(pattern, InputPattern::Random { seed: Some(9) });
+
+		let mut pattern = InputPattern::Random { seed: Some(3) };
+		assert_eq!(pattern.settle_seed(None), Some(3));
+
+		let mut pattern = InputPattern::Random { seed: None };
+		let settled = pattern.settle_seed(None);
+		assert!(settled.is_some());
+		assert_eq!(pattern, InputPattern::Random { seed: settled });
+
+		let mut pattern = InputPattern::Ramp;
+		assert_eq!(pattern.settle_seed(Some(9)), None);
+		assert_eq!(pattern, InputPattern::Ramp);
+	}
+
+	#[test]
+	fn nth_counts_seeds_up_and_numbers_files() {
+		let random = InputPattern::Random {
+			seed: Some(u64::MAX),
+		};
+		assert_eq!(random.nth(0), random);
+		assert_eq!(random.nth(2), InputPattern::Random { seed: Some(1) });
+
+		let file = InputPattern::FromFile(PathBuf::from("data/input.bin"));
+		assert_eq!(
+			file.nth(3),
+			InputPattern::FromFile(PathBuf::from("data/input.3.bin"))
+		);
+
+		assert_eq!(
+			InputPattern::Constant(1.5).nth(4),
+			InputPattern::Constant(1.5)
+		);
+	}
+
+	#[test]
+	fn a_seed_always_gives_the_same_values() {
+		let values = generate_input(&InputPattern::Random { seed: Some(7) }, 4).unwrap();
+		let bits = values.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+		assert_eq!(bits, [1054167542, 1022909440, 1041365372, 1050491496]);
+	}
+
+	#[test]
+	fn ramps_count_up_by_halves() {
+		assert_eq!(generate_ramp(4), [0.0, 0.5, 1.0, 1.5]);
+		assert_eq!(
+			generate_input(&InputPattern::Ramp, 4).unwrap(),
+			generate_ramp(4)
+		);
+	}
+
+	#[test]
+	fn from_file_needs_exactly_one_float_per_element() {
+		let path =
+			std::env::temp_dir().join(format!("vk_compute_input_test_{}.bin", std::process::id()));
+		let bytes = [1.0f32, 2.0, 3.0]
+			.iter()
+			.flat_map(|v| v.to_le_bytes())
+			.collect::<Vec<_>>();
+		fs::write(&path, bytes).unwrap();
+
+		let pattern = InputPattern::FromFile(path.clone());
+		let read = generate_input(&pattern, 3);
+		let too_short = generate_input(&pattern, 4);
+		fs::remove_file(&path).unwrap();
+
+		assert_eq!(read.unwrap(), [1.0, 2.0, 3.0]);
+		let message = too_short.unwrap_err().to_string();
+		assert!(
+			message.contains("is 12 bytes, expected 16 (4 elements)"),
+			"{}",
+			message
+		);
+	}
+}
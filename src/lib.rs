@@ -1,67 +1,506 @@
+#![allow(clippy::missing_safety_doc)]
+
 use anyhow::{anyhow, Result};
-use owo_colors::OwoColorize;
+use half::f16;
+use log::{debug, info, warn};
+use owo_colors::{OwoColorize, Stream};
 use serde::Deserialize;
+use std::borrow::Cow;
+use std::env;
 use std::fs;
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+
+use crate::input::InputPattern;
+use crate::output::OutputFormat;
 use vulkanalia::prelude::v1_1::*;
 
+/// A guard for a `tracing` span covering one setup phase, or nothing without the feature.
+#[cfg(feature = "tracing")]
+macro_rules! phase {
+	($name:literal) => {
+		tracing::info_span!($name).entered()
+	};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! phase {
+	($name:literal) => {
+		()
+	};
+}
+
+pub mod app;
+pub mod compare;
+pub mod context;
+pub mod host;
+pub mod info;
+pub mod input;
+pub mod output;
+pub mod progress;
+pub mod reflect;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod worker;
+
+/// Kernels compiled by build.rs from `src/shaders/*.comp`, keyed by file stem.
+pub mod shaders {
+	include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+
+	pub fn by_name(name: &str) -> Option<&'static [u8]> {
+		SHADERS
+			.iter()
+			.find(|(shader_name, _)| *shader_name == name)
+			.map(|(_, bytecode)| *bytecode)
+	}
+
+	pub fn names() -> impl Iterator<Item = &'static str> {
+		SHADERS.iter().map(|(name, _)| *name)
+	}
+}
+
+/// The failures a caller might want to tell apart. Functions here still return
+/// `anyhow::Error`s, often with context added, so find these with
+/// `error.downcast_ref::<ComputeError>()`.
+#[derive(Debug, Error)]
+pub enum ComputeError {
+	/// the Vulkan runtime isn't installed, or isn't where the dynamic linker looks
+	#[error("Couldn't load the Vulkan loader {library}: {reason}. {}", LOADER_HINT)]
+	NoLoader {
+		library: &'static str,
+		reason: String,
+	},
+	#[error("Missing suitable physical device.")]
+	NoSuitableDevice,
+	#[error("Missing suitable compute queue.")]
+	NoComputeQueue,
+	#[error("Missing memory type.")]
+	NoMemoryType,
+	/// an extension or feature the configuration needs
+	#[error("Missing {0}.")]
+	Unsupported(&'static str),
+	#[error(transparent)]
+	Allocation(#[from] HeapTooSmallError),
+	#[error("{0}")]
+	ShaderModule(&'static str),
+	/// nothing but teardown will work on the device after this
+	#[error(
+		"Device lost while {0}. The shader most likely faulted; run a debug build so the \
+		 validation layers are on, ideally with GPU-assisted validation, to find the bad access."
+	)]
+	DeviceLost(&'static str),
+	#[error(transparent)]
+	Vulkan(#[from] vk::ErrorCode),
+}
+
+impl ComputeError {
+	/// Whether `error` is, or wraps, a [`ComputeError::DeviceLost`].
+	pub fn is_device_lost(error: &anyhow::Error) -> bool {
+		matches!(
+			error.downcast_ref::<ComputeError>(),
+			Some(ComputeError::DeviceLost(_))
+		)
+	}
+
+	/// Whether `error` is, or wraps, running out of device memory, whether the heaps were
+	/// seen to be too small up front or the driver said so.
+	pub fn is_out_of_memory(error: &anyhow::Error) -> bool {
+		matches!(
+			error.downcast_ref::<ComputeError>(),
+			Some(
+				ComputeError::Allocation(_)
+					| ComputeError::Vulkan(vk::ErrorCode::OUT_OF_DEVICE_MEMORY)
+			)
+		)
+	}
+}
+
+#[cfg(target_os = "windows")]
+const LOADER_HINT: &str = "It comes with your GPU vendor's driver; update that, or install \
+	the Vulkan SDK (https://vulkan.lunarg.com/sdk/home) for vulkan-1.dll.";
+#[cfg(target_os = "macos")]
+const LOADER_HINT: &str = "macOS needs MoltenVK and the loader, from the Vulkan SDK \
+	(https://vulkan.lunarg.com/sdk/home) or `brew install molten-vk vulkan-loader`; \
+	set DYLD_LIBRARY_PATH if they're somewhere unusual.";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const LOADER_HINT: &str = "Install it and a driver with your package manager, e.g. \
+	`apt install libvulkan1 mesa-vulkan-drivers` or `dnf install vulkan-loader \
+	mesa-vulkan-drivers`; Mesa's lavapipe runs on the CPU where there's no GPU driver.";
+
+/// Singles out `DEVICE_LOST`, after which nothing but teardown will work on the device.
+pub fn device_error(code: vk::ErrorCode, during: &'static str) -> ComputeError {
+	if code == vk::ErrorCode::DEVICE_LOST {
+		ComputeError::DeviceLost(during)
+	} else {
+		ComputeError::Vulkan(code)
+	}
+}
+
 #[derive(Debug, Error)]
-#[error("Missing {0}.")]
-pub struct SuitabilityError(pub &'static str);
+#[error(
+	"requested {} MiB ({requested} bytes) but the heap has only {} MiB ({available} bytes) \
+	 {}, {} bytes short",
+	requested / MIB,
+	available / MIB,
+	if *budgeted { "left in its budget" } else { "available" },
+	requested - available
+)]
+pub struct HeapTooSmallError {
+	pub requested: u64,
+	pub available: u64,
+	/// whether `available` is what's left of the heap's budget rather than its size
+	pub budgeted: bool,
+}
 
+/// `heap_sizes` is what each heap can offer, see [`get_heap_sizes`].
 pub fn get_best_memory_type_index(
 	properties: &vk::PhysicalDeviceMemoryProperties,
+	heap_sizes: &HeapSizes,
 	desired_flags: vk::MemoryPropertyFlags,
 	desired_size: usize,
+) -> Result<u32> {
+	let suitable_types = (0..properties.memory_type_count).filter(|i| {
+		let memory_type = properties.memory_types[*i as usize];
+		memory_type.property_flags.contains(desired_flags)
+	});
+	let heap_size_of =
+		|i: u32| heap_sizes.sizes[properties.memory_types[i as usize].heap_index as usize];
+
+	if let Some(index) = suitable_types
+		.clone()
+		.find(|i| desired_size as u64 <= heap_size_of(*i))
+	{
+		return Ok(index);
+	}
+
+	match suitable_types.map(heap_size_of).max() {
+		Some(available) => Err(anyhow!(ComputeError::Allocation(HeapTooSmallError {
+			requested: desired_size as u64,
+			available,
+			budgeted: heap_sizes.budgeted,
+		}))),
+		None => Err(anyhow!(ComputeError::NoMemoryType)),
+	}
+}
+
+/// The first of `candidates` that [`get_best_memory_type_index`] finds a type for, so
+/// callers can ask for the memory they'd like and settle for what they can get. The
+/// last candidate's error is returned if none pans out.
+pub fn get_first_memory_type_index(
+	properties: &vk::PhysicalDeviceMemoryProperties,
+	heap_sizes: &HeapSizes,
+	candidates: &[vk::MemoryPropertyFlags],
+	desired_size: usize,
+) -> Result<u32> {
+	let mut result = Err(anyhow!(ComputeError::NoMemoryType));
+	for flags in candidates {
+		result = get_best_memory_type_index(properties, heap_sizes, *flags, desired_size);
+		if result.is_ok() {
+			break;
+		}
+	}
+	result
+}
+
+/// How much of a heap this process may use, and how much of that it already does.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapBudget {
+	pub budget: vk::DeviceSize,
+	pub usage: vk::DeviceSize,
+}
+
+impl HeapBudget {
+	pub fn free(&self) -> vk::DeviceSize {
+		self.budget.saturating_sub(self.usage)
+	}
+}
+
+/// Each heap's budget, or `None` without `VK_EXT_memory_budget`.
+pub unsafe fn get_heap_budgets(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Result<Option<Vec<HeapBudget>>> {
+	if !has_device_extension(
+		instance,
+		physical_device,
+		&vk::EXT_MEMORY_BUDGET_EXTENSION.name,
+	)? {
+		return Ok(None);
+	}
+
+	let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+	let mut properties = vk::PhysicalDeviceMemoryProperties2::builder()
+		.push_next(&mut budget)
+		.build();
+	instance.get_physical_device_memory_properties2(physical_device, &mut properties);
+
+	let heap_count = properties.memory_properties.memory_heap_count as usize;
+	Ok(Some(
+		(0..heap_count)
+			.map(|i| HeapBudget {
+				budget: budget.heap_budget[i],
+				usage: budget.heap_usage[i],
+			})
+			.collect(),
+	))
+}
+
+const MIB: u64 = 1024 * 1024;
+
+/// What each heap can offer, see [`get_heap_sizes`].
+#[derive(Clone, Debug)]
+pub struct HeapSizes {
+	pub sizes: Vec<vk::DeviceSize>,
+	/// whether `sizes` are what's left of each heap's budget rather than its total size
+	pub budgeted: bool,
+}
+
+impl HeapSizes {
+	/// Checks `requested` bytes fit in heap `heap_index` before they're allocated, so
+	/// running out says by how much rather than just `ERROR_OUT_OF_DEVICE_MEMORY`.
+	pub fn check(&self, heap_index: u32, requested: vk::DeviceSize) -> Result<()> {
+		let available = self.sizes[heap_index as usize];
+		debug!(
+			"allocating {} bytes from heap {}, which has {} MiB{}",
+			requested,
+			heap_index,
+			available / MIB,
+			if self.budgeted {
+				" left in its budget"
+			} else {
+				""
+			}
+		);
+		if requested > available {
+			return Err(anyhow!(ComputeError::Allocation(HeapTooSmallError {
+				requested,
+				available,
+				budgeted: self.budgeted,
+			})));
+		}
+		Ok(())
+	}
+}
+
+/// The space each heap can offer: what's left of its budget with `VK_EXT_memory_budget`,
+/// since this and other processes may already hold some of it, or its total size without.
+pub unsafe fn get_heap_sizes(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Result<HeapSizes> {
+	if let Some(budgets) = get_heap_budgets(instance, physical_device)? {
+		return Ok(HeapSizes {
+			sizes: budgets.iter().map(HeapBudget::free).collect(),
+			budgeted: true,
+		});
+	}
+
+	let properties = instance.get_physical_device_memory_properties(physical_device);
+	Ok(HeapSizes {
+		sizes: properties.memory_heaps[..properties.memory_heap_count as usize]
+			.iter()
+			.map(|heap| heap.size)
+			.collect(),
+		budgeted: false,
+	})
+}
+
+/// Rounds `value` up to a multiple of `alignment`, which Vulkan guarantees is a power of two.
+/// `None` if that doesn't fit in a `DeviceSize`.
+pub fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+	if alignment == 0 {
+		Some(value)
+	} else {
+		value
+			.checked_add(alignment - 1)
+			.map(|padded| padded & !(alignment - 1))
+	}
+}
+
+/// Setup stages and how long each took, in the order they finished.
+#[derive(Clone, Debug)]
+pub struct StageTimings {
+	pub stages: Vec<(&'static str, Duration)>,
+	since: Instant,
+}
+
+impl StageTimings {
+	pub fn start() -> Self {
+		Self {
+			stages: Vec::new(),
+			since: Instant::now(),
+		}
+	}
+
+	/// Ends `stage`, which began when the previous one ended or timing started.
+	pub fn end(&mut self, stage: &'static str) {
+		let now = Instant::now();
+		self.stages.push((stage, now - self.since));
+		self.since = now;
+	}
+}
+
+/// The bytes of `count` elements of `element_size`, or an error where plain arithmetic
+/// would wrap around to a too-small size.
+pub fn checked_size(count: usize, element_size: usize) -> Result<vk::DeviceSize> {
+	count
+		.checked_mul(element_size)
+		.and_then(|size| vk::DeviceSize::try_from(size).ok())
+		.ok_or_else(|| {
+			anyhow!(
+				"{} elements of {} bytes is more than a buffer can hold",
+				count,
+				element_size
+			)
+		})
+}
+
+/// Like [`get_best_memory_type_index`], restricted to the types a resource can live in.
+pub fn get_memory_type_index_for_requirements(
+	properties: &vk::PhysicalDeviceMemoryProperties,
+	requirements: &vk::MemoryRequirements,
+	desired_flags: vk::MemoryPropertyFlags,
 ) -> Result<u32> {
 	(0..properties.memory_type_count)
 		.find(|i| {
 			let memory_type = properties.memory_types[*i as usize];
-			let memory_heap = properties.memory_heaps[memory_type.heap_index as usize];
-			let right_properties = memory_type.property_flags.contains(desired_flags);
-			let right_size = desired_size as u64 <= memory_heap.size;
-			right_properties && right_size
+			let allowed = requirements.memory_type_bits & (1 << i) != 0;
+			allowed && memory_type.property_flags.contains(desired_flags)
 		})
-		.ok_or_else(|| anyhow!(SuitabilityError("memory type")))
+		.ok_or_else(|| anyhow!(ComputeError::NoMemoryType))
 }
 
 const HAS_COMPUTE: fn(&vk::QueueFamilyProperties) -> bool =
 	|p| p.queue_flags.contains(vk::QueueFlags::COMPUTE);
 
+/// Only what the family advertises; compute and graphics families can run transfer
+/// commands whether or not they say so.
+const REPORTS_TRANSFER: fn(&vk::QueueFamilyProperties) -> bool =
+	|p| p.queue_flags.contains(vk::QueueFlags::TRANSFER);
+
+const HAS_TIMESTAMPS: fn(&vk::QueueFamilyProperties) -> bool = |p| p.timestamp_valid_bits > 0;
+
+const IS_DEDICATED_COMPUTE: fn(&vk::QueueFamilyProperties) -> bool =
+	|p| HAS_COMPUTE(p) && !p.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+
+const IS_DEDICATED_TRANSFER: fn(&vk::QueueFamilyProperties) -> bool = |p| {
+	p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+		&& !p
+			.queue_flags
+			.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+};
+
 pub unsafe fn pick_physical_device(
 	instance: &Instance,
-	config: &DeviceConfig,
+	preference: DevicePreference,
 ) -> Result<vk::PhysicalDevice> {
+	let mut first_compute_device = None;
+	// a pinned device_id that turned up without compute, to tell apart from not found
+	let mut unusable_match = None;
+	let wanted_uuid = match preference {
+		DevicePreference::Uuid(uuid) => Some(uuid),
+		_ => None,
+	};
 	for physical_device in instance.enumerate_physical_devices()? {
 		let props = instance.get_physical_device_properties(physical_device);
-		println!(
+		debug!(
 			"found device with vendor_id {:x} and device_id {:x} that is named {}",
-			(props.vendor_id).green(),
-			(props.device_id).green(),
-			(props.device_name).bright_blue()
+			(props.vendor_id).if_supports_color(Stream::Stderr, |t| t.green()),
+			(props.device_id).if_supports_color(Stream::Stderr, |t| t.green()),
+			(props.device_name).if_supports_color(Stream::Stderr, |t| t.bright_blue())
 		);
+		let uuid_matches =
+			wanted_uuid.is_some() && get_device_uuid(instance, physical_device) == wanted_uuid;
 
-		if !has_compute_queue(&instance, physical_device) {
+		if !has_compute_queue(instance, physical_device) {
+			if matches!(preference, DevicePreference::Id(id) if id == props.device_id) {
+				warn!(
+					"device {} matched device_id {:x} but has no compute queue; skipping",
+					(props.device_name).if_supports_color(Stream::Stderr, |t| t.bright_blue()),
+					props.device_id
+				);
+				unusable_match = Some(props.device_id);
+			}
+			if uuid_matches {
+				unusable_match = Some(props.device_id);
+			}
 			continue;
 		}
+		first_compute_device.get_or_insert(physical_device);
 
-		if config.first_device {
-			println!(
-				"using first available device {}",
-				(props.device_name).bright_blue()
-			);
-			return Ok(physical_device);
-		} else if None == config.device_id {
-			return Err(anyhow!("must specify either a device_id or first_device"));
-		} else if props.device_id == config.device_id.unwrap() {
-			println!(
-				"using selected device {}",
-				(props.device_name).bright_blue()
-			);
-			return Ok(physical_device);
+		match preference {
+			DevicePreference::First => {
+				info!(
+					"using first available device {}",
+					(props.device_name).if_supports_color(Stream::Stderr, |t| t.bright_blue())
+				);
+				return Ok(physical_device);
+			}
+			DevicePreference::Id(device_id) if props.device_id == device_id => {
+				info!(
+					"using selected device {}",
+					(props.device_name).if_supports_color(Stream::Stderr, |t| t.bright_blue())
+				);
+				return Ok(physical_device);
+			}
+			DevicePreference::Id(_) => {}
+			DevicePreference::Uuid(_) if uuid_matches => {
+				info!(
+					"using device {}",
+					(props.device_name).if_supports_color(Stream::Stderr, |t| t.bright_blue())
+				);
+				return Ok(physical_device);
+			}
+			DevicePreference::Uuid(_) => {}
+			DevicePreference::Cpu if props.device_type == vk::PhysicalDeviceType::CPU => {
+				info!(
+					"using software device {}",
+					(props.device_name).if_supports_color(Stream::Stderr, |t| t.bright_blue())
+				);
+				return Ok(physical_device);
+			}
+			DevicePreference::Cpu => {}
+		}
+	}
+
+	if let Some(device_id) = unusable_match {
+		let error = anyhow!(ComputeError::NoComputeQueue);
+		return Err(error.context(format!(
+			"device_id {:x} was found but has no compute queue",
+			device_id
+		)));
+	}
+
+	match (preference, first_compute_device) {
+		(DevicePreference::Cpu, Some(physical_device)) => {
+			warn!("no software device found, falling back to the first compute device");
+			Ok(physical_device)
 		}
+		_ => Err(anyhow!(ComputeError::NoSuitableDevice)),
+	}
+}
+
+/// The device's `deviceUUID`, or `None` for a Vulkan 1.0 device, which can't report one.
+pub unsafe fn get_device_uuid(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Option<[u8; vk::UUID_SIZE]> {
+	let api_version = instance
+		.get_physical_device_properties(physical_device)
+		.api_version;
+	if api_version < vk::make_version(1, 1, 0) {
+		return None;
 	}
-	Err(anyhow!(SuitabilityError("suitable physical device")))
+	let mut id = vk::PhysicalDeviceIDProperties::default();
+	let mut properties = vk::PhysicalDeviceProperties2::builder()
+		.push_next(&mut id)
+		.build();
+	instance.get_physical_device_properties2(physical_device, &mut properties);
+	Some(id.device_uuid.0)
 }
 
 pub unsafe fn has_compute_queue(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
@@ -71,18 +510,923 @@ pub unsafe fn has_compute_queue(instance: &Instance, physical_device: vk::Physic
 
 #[derive(Deserialize)]
 pub struct Config {
+	#[serde(default)]
 	pub device: DeviceConfig,
+	#[serde(default)]
+	pub features: FeatureConfig,
+	#[serde(default)]
+	pub compute: ComputeConfig,
+	#[serde(default)]
+	pub application: ApplicationConfig,
+}
+
+/// How the instance introduces itself to drivers and tools like RenderDoc, and what
+/// else it turns on.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ApplicationConfig {
+	pub name: String,
+	/// major, minor, patch
+	pub version: [u32; 3],
+	/// more instance extensions to enable where the loader offers them
+	pub instance_extensions: Vec<String>,
+}
+
+impl Default for ApplicationConfig {
+	fn default() -> Self {
+		Self {
+			name: String::from("VKFromFileComputeSample"),
+			version: [1, 0, 0],
+			instance_extensions: Vec::new(),
+		}
+	}
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ComputeConfig {
+	/// read the workgroup counts from a buffer with `cmd_dispatch_indirect`
+	pub indirect_dispatch: bool,
+	pub input_pattern: InputPattern,
+	/// where to write the results after readback, if anywhere
+	pub output_path: Option<PathBuf>,
+	pub output_format: OutputFormat,
+	/// shaders run in order, each reading the previous one's output; empty runs the
+	/// bundled shader once
+	pub passes: Vec<PassConfig>,
+	/// how long to wait for the dispatch to finish before giving up on it
+	pub fence_timeout_ms: u64,
+	/// further waits of `fence_timeout_ms` each before treating the shader as hung
+	pub fence_wait_retries: u32,
+	/// buffers bound after the input and output ones, at bindings 2, 3...
+	pub bindings: Vec<BindingConfig>,
+	pub element_type: ElementType,
+	/// positions from one element to the next in the input and output buffers, for a
+	/// shader reading one component of interleaved data, e.g. 4 for a `vec4` array
+	pub element_stride: u32,
+	/// which position within each `element_stride` holds the element
+	pub element_offset: u32,
+	/// clear the output buffer before dispatching; skip it if every element gets written
+	pub zero_output: bool,
+	/// width, height and depth of the data, for shaders indexing in 2D or 3D
+	pub dimensions: Option<[u32; 3]>,
+	/// the shaders' workgroup size, for working out how many groups to dispatch
+	pub local_size: [u32; 3],
+	/// count compute shader invocations with a pipeline statistics query
+	pub pipeline_statistics: bool,
+	/// log how long each pass took on the device, from timestamps written between them
+	pub pass_timings: bool,
+	/// pin every pipeline to this subgroup size with VK_EXT_subgroup_size_control
+	pub subgroup_size: Option<u32>,
+	/// put the input and output buffers in host-cached memory where there is some, for
+	/// faster readback; non-coherent memory is flushed and invalidated around each use
+	pub host_cached: bool,
+	/// how each pass waits for the one before it
+	pub pass_sync: PassSync,
+	/// upload the input through a staging buffer copied on the separate transfer queue,
+	/// which the dispatch waits on with a semaphore instead of the host waiting
+	pub staged_upload: bool,
+	/// the descriptor set holding the input and output buffers at bindings 0 and 1
+	pub descriptor_set: u32,
+	/// properties the input and output buffers' memory type needs, tried before the
+	/// usual host-coherent memory; overrides `host_cached`
+	pub memory_flags: Option<Vec<MemoryFlag>>,
+	/// usages for the input and output buffers beyond `STORAGE_BUFFER` and
+	/// `TRANSFER_DST`, which they always have for the shaders, the output clear and
+	/// `staged_upload`
+	pub buffer_usage: Vec<BufferUsage>,
+	/// when the buffers don't fit, halve the element count until they do and run the
+	/// input through in chunks; only for shaders where each element stands alone
+	pub chunk_on_oom: bool,
+	/// wait for the device to go idle before tearing down, so nothing still executing
+	/// loses a buffer or pipeline under it
+	pub wait_idle_on_destroy: bool,
+}
+
+impl Default for ComputeConfig {
+	fn default() -> Self {
+		Self {
+			indirect_dispatch: false,
+			input_pattern: InputPattern::default(),
+			output_path: None,
+			output_format: OutputFormat::default(),
+			passes: Vec::new(),
+			fence_timeout_ms: 5000,
+			fence_wait_retries: 0,
+			bindings: Vec::new(),
+			element_type: ElementType::default(),
+			element_stride: 1,
+			element_offset: 0,
+			zero_output: true,
+			dimensions: None,
+			local_size: [1, 1, 1],
+			pipeline_statistics: false,
+			pass_timings: false,
+			subgroup_size: None,
+			host_cached: false,
+			pass_sync: PassSync::default(),
+			staged_upload: false,
+			descriptor_set: 0,
+			memory_flags: None,
+			buffer_usage: Vec::new(),
+			chunk_on_oom: false,
+			wait_idle_on_destroy: true,
+		}
+	}
+}
+
+impl ComputeConfig {
+	/// `memory_flags` combined, with `HOST_VISIBLE` added if they leave it out: even
+	/// with `staged_upload` the results are read back through a mapping.
+	pub fn memory_property_flags(&self) -> Option<vk::MemoryPropertyFlags> {
+		self.configured_memory_flags()
+			.map(|flags| flags | vk::MemoryPropertyFlags::HOST_VISIBLE)
+	}
+
+	fn configured_memory_flags(&self) -> Option<vk::MemoryPropertyFlags> {
+		let flags = self
+			.memory_flags
+			.as_ref()?
+			.iter()
+			.fold(vk::MemoryPropertyFlags::empty(), |flags, flag| {
+				flags | flag.flags()
+			});
+		Some(flags)
+	}
+
+	/// Warns once, as the config is loaded, about what
+	/// [`ComputeConfig::memory_property_flags`] quietly overrides.
+	fn warn_about_memory_flags(&self) {
+		let Some(flags) = self.configured_memory_flags() else {
+			return;
+		};
+		if self.host_cached {
+			warn!("memory_flags is set, so host_cached is ignored");
+		}
+		if !flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+			warn!("memory_flags needs HOST_VISIBLE to upload and read back, adding it");
+		}
+	}
+
+	/// This config for the `index`th of several runs, with its own input (see
+	/// [`InputPattern::nth`]) and its own numbered output file.
+	pub fn nth_run(&self, index: usize) -> ComputeConfig {
+		ComputeConfig {
+			input_pattern: self.input_pattern.nth(index),
+			output_path: self
+				.output_path
+				.as_deref()
+				.map(|path| numbered_path(path, index)),
+			..self.clone()
+		}
+	}
+}
+
+/// `path` with `{}` replaced by `index`, or with `index` before the extension if it
+/// has no `{}`: `output.bin` becomes `output.3.bin`.
+pub fn numbered_path(path: &Path, index: usize) -> PathBuf {
+	let text = path.to_string_lossy();
+	if text.contains("{}") {
+		return PathBuf::from(text.replace("{}", &index.to_string()));
+	}
+	let mut name = path.file_stem().unwrap_or_default().to_os_string();
+	name.push(format!(".{}", index));
+	if let Some(extension) = path.extension() {
+		name.push(".");
+		name.push(extension);
+	}
+	path.with_file_name(name)
+}
+
+/// How chained passes are ordered within the command buffer.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PassSync {
+	/// a pipeline barrier between passes: everything before it finishes before anything
+	/// after it starts
+	#[default]
+	Barrier,
+	/// each pass sets an event the next one waits on. Only the work before the set has
+	/// to finish, so commands recorded between the two could overlap with it; with
+	/// nothing there yet it behaves like a barrier, at the cost of an event per pass
+	/// boundary and a host-side reset before every dispatch. Batches keep barriers,
+	/// since their jobs run at once and can't share events
+	Event,
+}
+
+/// A memory property the input and output buffers' memory type must have.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MemoryFlag {
+	DeviceLocal,
+	HostVisible,
+	HostCoherent,
+	HostCached,
+}
+
+impl MemoryFlag {
+	pub fn flags(self) -> vk::MemoryPropertyFlags {
+		match self {
+			MemoryFlag::DeviceLocal => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+			MemoryFlag::HostVisible => vk::MemoryPropertyFlags::HOST_VISIBLE,
+			MemoryFlag::HostCoherent => vk::MemoryPropertyFlags::HOST_COHERENT,
+			MemoryFlag::HostCached => vk::MemoryPropertyFlags::HOST_CACHED,
+		}
+	}
+}
+
+/// A usage to create a buffer with, on top of the ones it always has.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BufferUsage {
+	TransferSrc,
+	TransferDst,
+	UniformBuffer,
+	StorageBuffer,
+	IndirectBuffer,
+}
+
+impl BufferUsage {
+	pub fn flags(self) -> vk::BufferUsageFlags {
+		match self {
+			BufferUsage::TransferSrc => vk::BufferUsageFlags::TRANSFER_SRC,
+			BufferUsage::TransferDst => vk::BufferUsageFlags::TRANSFER_DST,
+			BufferUsage::UniformBuffer => vk::BufferUsageFlags::UNIFORM_BUFFER,
+			BufferUsage::StorageBuffer => vk::BufferUsageFlags::STORAGE_BUFFER,
+			BufferUsage::IndirectBuffer => vk::BufferUsageFlags::INDIRECT_BUFFER,
+		}
+	}
+
+	pub fn combined(usages: &[BufferUsage]) -> vk::BufferUsageFlags {
+		usages
+			.iter()
+			.fold(vk::BufferUsageFlags::empty(), |flags, usage| {
+				flags | usage.flags()
+			})
+	}
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PassConfig {
+	/// a kernel name from `src/shaders`, or a path to a SPIR-V file
+	pub shader: String,
+	/// values for specialization constants 0, 1, 2...
+	#[serde(default)]
+	pub specialization: Vec<u32>,
+}
+
+/// An extra buffer of `f32`s, e.g. a uniform block of kernel parameters. Uniform blocks
+/// use std140, so declare them as `vec4`s to match this tightly packed layout.
+#[derive(Deserialize, Clone, Debug)]
+pub struct BindingConfig {
+	pub kind: BufferKind,
+	/// left out for views, which take the viewed binding's
+	#[serde(default)]
+	pub element_count: usize,
+	/// initial contents, zero-padded to `element_count`
+	#[serde(default)]
+	pub values: Vec<f32>,
+	/// an earlier binding whose buffer this one binds a window of, rather than its own
+	#[serde(default)]
+	pub view_of: Option<u32>,
+	/// first element bound; in bytes it has to be a multiple of the device's
+	/// `minStorageBufferOffsetAlignment` or `minUniformBufferOffsetAlignment`
+	#[serde(default)]
+	pub offset: usize,
+	/// elements bound from `offset`, the rest of the buffer if left out
+	#[serde(default)]
+	pub range: Option<usize>,
+	/// the descriptor set it's bound in, `descriptor_set` if left out
+	#[serde(default)]
+	pub set: Option<u32>,
+	/// usages for the buffer beyond the one its `kind` needs; not for views
+	#[serde(default)]
+	pub usage: Vec<BufferUsage>,
+	/// read the buffer back after each dispatch, for storage buffers the shaders write;
+	/// not for views
+	#[serde(default)]
+	pub readback: bool,
+}
+
+/// How each input and output element is stored on the device. The host always works in
+/// `f32`, converting on upload and readback.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ElementType {
+	#[default]
+	F32,
+	/// needs `shaderFloat16` and `storageBuffer16BitAccess`
+	F16,
+	/// needs `shaderFloat64`; results come back rounded to `f32` like everything else
+	F64,
+}
+
+impl ElementType {
+	pub fn size(self) -> usize {
+		match self {
+			ElementType::F32 => size_of::<f32>(),
+			ElementType::F16 => size_of::<f16>(),
+			ElementType::F64 => size_of::<f64>(),
+		}
+	}
+
+	#[cfg(feature = "bytemuck")]
+	pub fn encode(self, values: &[f32]) -> Vec<u8> {
+		match self {
+			ElementType::F32 => bytemuck::cast_slice(values).to_vec(),
+			ElementType::F16 => {
+				let halves = values.iter().map(|v| f16::from_f32(*v)).collect::<Vec<_>>();
+				bytemuck::cast_slice(&halves).to_vec()
+			}
+			ElementType::F64 => {
+				let doubles = values.iter().map(|v| *v as f64).collect::<Vec<_>>();
+				bytemuck::cast_slice(&doubles).to_vec()
+			}
+		}
+	}
+
+	/// Trailing bytes short of a whole element are dropped.
+	#[cfg(feature = "bytemuck")]
+	pub fn decode(self, bytes: &[u8]) -> Vec<f32> {
+		let whole = bytes.len() - bytes.len() % self.size();
+		// collected rather than cast in place, since `bytes` needn't be aligned
+		match self {
+			ElementType::F32 => bytemuck::pod_collect_to_vec(&bytes[..whole]),
+			ElementType::F16 => bytemuck::pod_collect_to_vec::<u8, f16>(&bytes[..whole])
+				.into_iter()
+				.map(f16::to_f32)
+				.collect(),
+			ElementType::F64 => bytemuck::pod_collect_to_vec::<u8, f64>(&bytes[..whole])
+				.into_iter()
+				.map(|v| v as f32)
+				.collect(),
+		}
+	}
+
+	#[cfg(not(feature = "bytemuck"))]
+	pub fn encode(self, values: &[f32]) -> Vec<u8> {
+		match self {
+			ElementType::F32 => values.iter().flat_map(|v| v.to_ne_bytes()).collect(),
+			ElementType::F16 => values
+				.iter()
+				.flat_map(|v| f16::from_f32(*v).to_ne_bytes())
+				.collect(),
+			ElementType::F64 => values
+				.iter()
+				.flat_map(|v| (*v as f64).to_ne_bytes())
+				.collect(),
+		}
+	}
+
+	/// Trailing bytes short of a whole element are dropped.
+	#[cfg(not(feature = "bytemuck"))]
+	pub fn decode(self, bytes: &[u8]) -> Vec<f32> {
+		match self {
+			ElementType::F32 => bytes
+				.chunks_exact(self.size())
+				.map(|chunk| f32::from_ne_bytes(chunk.try_into().unwrap()))
+				.collect(),
+			ElementType::F16 => bytes
+				.chunks_exact(self.size())
+				.map(|chunk| f16::from_ne_bytes(chunk.try_into().unwrap()).to_f32())
+				.collect(),
+			ElementType::F64 => bytes
+				.chunks_exact(self.size())
+				.map(|chunk| f64::from_ne_bytes(chunk.try_into().unwrap()) as f32)
+				.collect(),
+		}
+	}
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BufferKind {
+	Storage,
+	Uniform,
+}
+
+impl BufferKind {
+	pub fn descriptor_type(self) -> vk::DescriptorType {
+		match self {
+			BufferKind::Storage => vk::DescriptorType::STORAGE_BUFFER,
+			BufferKind::Uniform => vk::DescriptorType::UNIFORM_BUFFER,
+		}
+	}
+}
+
+/// The shader "path" that means read SPIR-V from stdin, e.g. piped from a compiler.
+pub const STDIN_SHADER: &str = "-";
+
+/// Names the disassembler [`disassemble`] runs instead of `spirv-dis` on the `PATH`.
+pub const DISASSEMBLER_ENV: &str = "SPIRV_DIS";
+const DISASSEMBLER_DEFAULT: &str = "spirv-dis";
+const DISASSEMBLER_HINT: &str = "install spirv-dis from the Vulkan SDK \
+	(https://vulkan.lunarg.com/sdk/home) or your package manager (e.g. spirv-tools), \
+	or set SPIRV_DIS to its path";
+
+/// `spirv` as text, from piping it through `spirv-dis`.
+pub fn disassemble(spirv: &[u8]) -> Result<String> {
+	let disassembler =
+		env::var(DISASSEMBLER_ENV).unwrap_or_else(|_| DISASSEMBLER_DEFAULT.to_string());
+	let mut child = Command::new(&disassembler)
+		.arg("-")
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|e| {
+			anyhow!(
+				"couldn't run {} ({})\n{}",
+				disassembler,
+				e,
+				DISASSEMBLER_HINT
+			)
+		})?;
+	// spirv-dis reads all of its input before writing anything, so this can't deadlock
+	child
+		.stdin
+		.take()
+		.expect("stdin is piped")
+		.write_all(spirv)?;
+
+	let output = child.wait_with_output()?;
+	if !output.status.success() {
+		return Err(anyhow!(
+			"{} failed ({}): {}",
+			disassembler,
+			output.status,
+			String::from_utf8_lossy(&output.stderr).trim()
+		));
+	}
+	Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Names the compiler [`compile_glsl`] runs instead of `glslangValidator` on the `PATH`,
+/// the same variable build.rs reads.
+pub const VALIDATOR_ENV: &str = "GLSLANG_VALIDATOR";
+const VALIDATOR_DEFAULT: &str = "glslangValidator";
+const VALIDATOR_HINT: &str = "install glslangValidator from the Vulkan SDK \
+	(https://vulkan.lunarg.com/sdk/home) or your package manager (e.g. glslang-tools), \
+	or set GLSLANG_VALIDATOR to its path";
+
+/// Names the validator [`run_spirv_val`] runs instead of `spirv-val` on the `PATH`.
+pub const SPIRV_VAL_ENV: &str = "SPIRV_VAL";
+const SPIRV_VAL_DEFAULT: &str = "spirv-val";
+
+/// Runs `spirv-val` on the module at `path` for Vulkan 1.1, failing with whatever it
+/// reports. Returns false if there's no `spirv-val` to run.
+pub fn run_spirv_val(path: &Path) -> Result<bool> {
+	let validator = env::var(SPIRV_VAL_ENV).unwrap_or_else(|_| SPIRV_VAL_DEFAULT.to_string());
+	let output = match Command::new(&validator)
+		.arg("--target-env")
+		.arg("vulkan1.1")
+		.arg(path)
+		.output()
+	{
+		Ok(output) => output,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+		Err(e) => return Err(anyhow!("couldn't run {} ({})", validator, e)),
+	};
+
+	if !output.status.success() {
+		return Err(anyhow!(
+			"{} rejected {} ({})\n{}{}",
+			validator,
+			path.display(),
+			output.status,
+			String::from_utf8_lossy(&output.stdout).trim(),
+			String::from_utf8_lossy(&output.stderr).trim()
+		));
+	}
+	Ok(true)
+}
+
+/// SPIR-V for the GLSL compute shader at `source`, compiled the way build.rs does it.
+pub fn compile_glsl(source: &Path) -> Result<Vec<u8>> {
+	let validator = env::var(VALIDATOR_ENV).unwrap_or_else(|_| VALIDATOR_DEFAULT.to_string());
+	let spirv = env::temp_dir().join(format!("vk_compute-{}.spv", std::process::id()));
+	let output = Command::new(&validator)
+		.arg("-o")
+		.arg(&spirv)
+		.arg("-V100")
+		.arg(source)
+		.output()
+		.map_err(|e| anyhow!("couldn't run {} ({})\n{}", validator, e, VALIDATOR_HINT))?;
+
+	if !output.status.success() {
+		// glslangValidator reports compile errors on stdout, not stderr
+		return Err(anyhow!(
+			"{} failed to compile {} ({})\n{}{}",
+			validator,
+			source.display(),
+			output.status,
+			String::from_utf8_lossy(&output.stdout).trim(),
+			String::from_utf8_lossy(&output.stderr).trim()
+		));
+	}
+	let bytecode = fs::read(&spirv).map_err(|e| {
+		anyhow!(
+			"{} left no SPIR-V for {}: {}",
+			validator,
+			source.display(),
+			e
+		)
+	});
+	let _ = fs::remove_file(&spirv);
+	bytecode
+}
+
+/// A kernel compiled from `src/shaders`, a SPIR-V file, or stdin for [`STDIN_SHADER`].
+pub fn load_shader(name_or_path: &str) -> Result<Cow<'static, [u8]>> {
+	if name_or_path == STDIN_SHADER {
+		let mut bytecode = Vec::new();
+		io::stdin()
+			.read_to_end(&mut bytecode)
+			.map_err(|e| anyhow!("couldn't read a shader from stdin: {}", e))?;
+		return Ok(Cow::Owned(bytecode));
+	}
+
+	match shaders::by_name(name_or_path) {
+		Some(bytecode) => Ok(Cow::Borrowed(bytecode)),
+		None => fs::read(name_or_path)
+			.map(Cow::Owned)
+			.map_err(|e| anyhow!("couldn't load shader {}: {}", name_or_path, e)),
+	}
 }
 
 #[derive(Deserialize)]
+#[serde(default)]
 pub struct DeviceConfig {
-	first_device: bool,
-	device_id: Option<u32>,
+	/// take the first compute device; the default unless `device_id` is set
+	pub first_device: Option<bool>,
+	pub device_id: Option<u32>,
+	pub queue_selection: QueueSelection,
+	pub separate_transfer_queue: bool,
+	/// how the input and output buffers are shared with a separate transfer queue
+	pub buffer_sharing: BufferSharing,
+	/// pick a software implementation such as lavapipe or SwiftShader when there is one
+	pub prefer_cpu: bool,
+	/// prefer a compute queue family that can write timestamps, for GPU timing
+	pub prefer_timestamps: bool,
+	/// 0.0 to 1.0; lower leaves more of a shared GPU to the display
+	pub queue_priority: f32,
+}
+
+impl Default for DeviceConfig {
+	fn default() -> Self {
+		Self {
+			first_device: None,
+			device_id: None,
+			queue_selection: QueueSelection::default(),
+			separate_transfer_queue: false,
+			buffer_sharing: BufferSharing::default(),
+			prefer_cpu: false,
+			prefer_timestamps: false,
+			queue_priority: 1.0,
+		}
+	}
+}
+
+/// Set to anything but empty or `0` to prefer a software device, e.g. on CI runners.
+pub const PREFER_CPU_ENV: &str = "VK_COMPUTE_PREFER_CPU";
+
+impl DeviceConfig {
+	pub fn device_preference(&self) -> Result<DevicePreference> {
+		let prefer_cpu_env =
+			std::env::var(PREFER_CPU_ENV).is_ok_and(|value| !matches!(value.as_str(), "" | "0"));
+		if self.prefer_cpu || prefer_cpu_env {
+			return Ok(DevicePreference::Cpu);
+		}
+
+		self.validate()?;
+		Ok(match self.device_id {
+			Some(device_id) => DevicePreference::Id(device_id),
+			None => DevicePreference::First,
+		})
+	}
+
+	/// Catches combinations that can't pick a device, before anything touches Vulkan.
+	pub fn validate(&self) -> Result<()> {
+		match (self.first_device, self.device_id) {
+			(Some(true), Some(device_id)) => Err(anyhow!(
+				"first_device = true and device_id = {:x} ask for different devices, keep one",
+				device_id
+			)),
+			(Some(false), None) if !self.prefer_cpu => Err(anyhow!(
+				"first_device = false needs a device_id (or prefer_cpu) to pick a device by"
+			)),
+			_ => Ok(()),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DevicePreference {
+	/// the first device with a compute queue
+	#[default]
+	First,
+	/// the compute-capable device with this `device_id`
+	Id(u32),
+	/// the device with this `deviceUUID`, which tells apart identical adapters that
+	/// share a `device_id` and stays the same from one instance to the next; see
+	/// [`get_device_uuid`]
+	Uuid([u8; vk::UUID_SIZE]),
+	/// a `CPU`-type device, or failing that the first device with a compute queue
+	Cpu,
+}
+
+/// How the input and output buffers are shared between the compute family and a
+/// separate transfer family. Either way they're exclusive without a transfer queue.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BufferSharing {
+	/// `CONCURRENT` with both families listed, so no ownership transfers are needed
+	#[default]
+	Concurrent,
+	/// `EXCLUSIVE`, a staged upload handing the input buffer over with a release and
+	/// an acquire barrier
+	Exclusive,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueSelection {
+	/// the first family that supports compute, whatever else it supports
+	#[default]
+	First,
+	/// a compute family without graphics, if the device has one
+	Dedicated,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct FeatureConfig {
+	pub shader_float64: bool,
+	pub shader_int16: bool,
+	pub shader_int8: bool,
+	pub shader_float16: bool,
+	pub storage_buffer_16bit_access: bool,
+	/// hand the shader the input and output buffers' addresses as push constants
+	pub buffer_device_address: bool,
+	/// `VK_KHR_shader_clock`'s subgroup clock, and its device clock where there is one
+	pub shader_clock: bool,
+	/// more device extensions to enable where the device offers them
+	pub device_extensions: Vec<String>,
+}
+
+/// Loads `path`, with the `[profiles.<name>]` table for `profile` (or the file's
+/// `default_profile`) merged over the base sections.
+pub fn get_config(path: &Path, profile: Option<&str>) -> Result<Config> {
+	let contents =
+		fs::read_to_string(path).map_err(|e| anyhow!("couldn't load {}: {}", path.display(), e))?;
+	let mut base: toml::value::Table = toml::from_str(&contents)
+		.map_err(|e| anyhow!("couldn't parse {}: {}", path.display(), e))?;
+
+	let default_profile = match base.remove("default_profile") {
+		Some(toml::Value::String(name)) => Some(name),
+		Some(_) => {
+			return Err(anyhow!(
+				"default_profile in {} isn't a string",
+				path.display()
+			))
+		}
+		None => None,
+	};
+	let mut profiles = match base.remove("profiles") {
+		Some(toml::Value::Table(profiles)) => profiles,
+		Some(_) => return Err(anyhow!("profiles in {} isn't a table", path.display())),
+		None => toml::value::Table::new(),
+	};
+	if let Some(name) = profile.or(default_profile.as_deref()) {
+		match profiles.remove(name) {
+			Some(toml::Value::Table(overrides)) => merge_table(&mut base, overrides),
+			Some(_) => return Err(anyhow!("profiles.{} isn't a table", name)),
+			None => {
+				let mut names = profiles.keys().map(String::as_str).collect::<Vec<_>>();
+				names.sort_unstable();
+				return Err(anyhow!(
+					"no profile {} in {} (there's {})",
+					name,
+					path.display(),
+					if names.is_empty() {
+						"none".to_owned()
+					} else {
+						names.join(", ")
+					}
+				));
+			}
+		}
+		info!("using config profile {}", name);
+	}
+
+	// toml 0.5 can't deserialize enums out of a Value (input_pattern's
+	// { random = { seed = 7 } } comes out as a unit variant), so go through JSON
+	let config: Config = serde_json::to_value(toml::Value::Table(base))
+		.and_then(serde_json::from_value)
+		.map_err(|e| anyhow!("couldn't parse {}: {}", path.display(), e))?;
+	config
+		.device
+		.validate()
+		.map_err(|e| anyhow!("invalid [device] in {}: {}", path.display(), e))?;
+	config.compute.warn_about_memory_flags();
+	Ok(config)
+}
+
+/// Tables merge key by key; anything else in `overrides` replaces what's in `base`.
+fn merge_table(base: &mut toml::value::Table, overrides: toml::value::Table) {
+	for (key, value) in overrides {
+		match (base.get_mut(&key), value) {
+			(Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+				merge_table(base, overrides)
+			}
+			(_, value) => {
+				base.insert(key, value);
+			}
+		}
+	}
+}
+
+pub unsafe fn has_device_extension(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+	name: &vk::ExtensionName,
+) -> Result<bool> {
+	let extension_properties =
+		instance.enumerate_device_extension_properties(physical_device, None)?;
+	Ok(extension_properties
+		.iter()
+		.any(|p| p.extension_name == *name))
+}
+
+/// Provisional, so vulkanalia only names it with its `provisional` feature.
+pub const VK_KHR_PORTABILITY_SUBSET: vk::ExtensionName =
+	vk::ExtensionName::from_bytes(b"VK_KHR_portability_subset");
+
+/// Whether the device only implements the portability subset of Vulkan, as MoltenVK
+/// does, and has to be told it's being used that way.
+pub unsafe fn has_portability_subset_extension(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Result<bool> {
+	has_device_extension(instance, physical_device, &VK_KHR_PORTABILITY_SUBSET)
+}
+
+/// `VK_KHR_shader_clock`'s features, all false where the device doesn't have it.
+pub unsafe fn get_shader_clock_features(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Result<vk::PhysicalDeviceShaderClockFeaturesKHR> {
+	let mut shader_clock = vk::PhysicalDeviceShaderClockFeaturesKHR::default();
+	if has_device_extension(
+		instance,
+		physical_device,
+		&vk::KHR_SHADER_CLOCK_EXTENSION.name,
+	)? {
+		let mut features = vk::PhysicalDeviceFeatures2::builder()
+			.push_next(&mut shader_clock)
+			.build();
+		instance.get_physical_device_features2(physical_device, &mut features);
+	}
+	Ok(shader_clock)
+}
+
+pub unsafe fn verify_requested_features(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+	config: &FeatureConfig,
+) -> Result<()> {
+	let has_float16_int8 = has_device_extension(
+		instance,
+		physical_device,
+		&vk::KHR_SHADER_FLOAT16_INT8_EXTENSION.name,
+	)?;
+
+	let has_buffer_device_address = has_device_extension(
+		instance,
+		physical_device,
+		&vk::KHR_BUFFER_DEVICE_ADDRESS_EXTENSION.name,
+	)?;
+
+	let mut float16_int8 = vk::PhysicalDeviceShaderFloat16Int8Features::default();
+	let mut storage_16bit = vk::PhysicalDevice16BitStorageFeatures::default();
+	let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+	let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut storage_16bit);
+	if has_float16_int8 {
+		features = features.push_next(&mut float16_int8);
+	}
+	if has_buffer_device_address {
+		features = features.push_next(&mut buffer_device_address);
+	}
+	let mut features = features.build();
+	instance.get_physical_device_features2(physical_device, &mut features);
+	let shader_clock = get_shader_clock_features(instance, physical_device)?;
+
+	let supported = features.features;
+	let requested = [
+		(
+			"shader_float64",
+			config.shader_float64,
+			supported.shader_float64,
+		),
+		("shader_int16", config.shader_int16, supported.shader_int16),
+		("shader_int8", config.shader_int8, float16_int8.shader_int8),
+		(
+			"shader_float16",
+			config.shader_float16,
+			float16_int8.shader_float16,
+		),
+		(
+			"storage_buffer_16bit_access",
+			config.storage_buffer_16bit_access,
+			storage_16bit.storage_buffer_16bit_access,
+		),
+		(
+			"buffer_device_address",
+			config.buffer_device_address,
+			buffer_device_address.buffer_device_address,
+		),
+		// the device clock is a bonus, enabled only where it's there
+		(
+			"shader_clock",
+			config.shader_clock,
+			shader_clock.shader_subgroup_clock,
+		),
+	];
+
+	let missing = requested
+		.iter()
+		.filter(|(_, wanted, available)| *wanted && *available != vk::TRUE)
+		.map(|(name, _, _)| *name)
+		.collect::<Vec<_>>();
+
+	if missing.is_empty() {
+		Ok(())
+	} else {
+		Err(anyhow!(
+			"device does not support requested feature(s): {}",
+			missing.join(", ")
+		))
+	}
 }
 
-pub fn get_config() -> Result<Config, toml::de::Error> {
-	let contents = fs::read_to_string("config.toml").expect("couldn't load config.toml");
-	toml::from_str(&contents)
+/// Checks `size` is a subgroup size compute pipelines can require on this device, through
+/// `VK_EXT_subgroup_size_control`.
+pub unsafe fn verify_subgroup_size(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+	size: u32,
+) -> Result<()> {
+	if !has_device_extension(
+		instance,
+		physical_device,
+		&vk::EXT_SUBGROUP_SIZE_CONTROL_EXTENSION.name,
+	)? {
+		return Err(anyhow!(ComputeError::Unsupported(
+			"VK_EXT_subgroup_size_control"
+		)));
+	}
+
+	let mut features = vk::PhysicalDeviceSubgroupSizeControlFeaturesEXT::default();
+	let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+		.push_next(&mut features)
+		.build();
+	instance.get_physical_device_features2(physical_device, &mut features2);
+	if features.subgroup_size_control != vk::TRUE {
+		return Err(anyhow!(
+			"device does not support requested feature(s): subgroup_size_control"
+		));
+	}
+
+	let mut properties = vk::PhysicalDeviceSubgroupSizeControlPropertiesEXT::default();
+	let mut properties2 = vk::PhysicalDeviceProperties2::builder()
+		.push_next(&mut properties)
+		.build();
+	instance.get_physical_device_properties2(physical_device, &mut properties2);
+	info!(
+		"device subgroups can be {} to {} wide",
+		properties.min_subgroup_size, properties.max_subgroup_size
+	);
+
+	if !properties
+		.required_subgroup_size_stages
+		.contains(vk::ShaderStageFlags::COMPUTE)
+	{
+		return Err(anyhow!(
+			"device can't require a subgroup size for compute shaders"
+		));
+	}
+	if !size.is_power_of_two()
+		|| size < properties.min_subgroup_size
+		|| size > properties.max_subgroup_size
+	{
+		return Err(anyhow!(
+			"subgroup_size {} isn't a power of two from {} to {}",
+			size,
+			properties.min_subgroup_size,
+			properties.max_subgroup_size
+		));
+	}
+	Ok(())
 }
 
 pub unsafe fn get_first_compute_queue_family_index(
@@ -91,25 +1435,177 @@ pub unsafe fn get_first_compute_queue_family_index(
 ) -> Result<u32> {
 	let properties = instance.get_physical_device_queue_family_properties(physical_device);
 
-	let maybe_index = properties.iter().position(HAS_COMPUTE).map(|i| i as u32);
+	// one that says it can copy and fill too, where the first compute family doesn't
+	let maybe_index = properties
+		.iter()
+		.position(|p| HAS_COMPUTE(p) && REPORTS_TRANSFER(p))
+		.or_else(|| properties.iter().position(HAS_COMPUTE))
+		.map(|i| i as u32);
 
 	if let Some(maybe_index) = maybe_index {
 		Ok(maybe_index)
 	} else {
-		Err(anyhow!(SuitabilityError("suitable compute queue")))
+		Err(anyhow!(ComputeError::NoComputeQueue))
 	}
 }
 
+pub unsafe fn get_dedicated_compute_queue_family_index(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Result<u32> {
+	let properties = instance.get_physical_device_queue_family_properties(physical_device);
+
+	properties
+		.iter()
+		.position(|p| IS_DEDICATED_COMPUTE(p) && REPORTS_TRANSFER(p))
+		.or_else(|| properties.iter().position(IS_DEDICATED_COMPUTE))
+		.or_else(|| properties.iter().position(HAS_COMPUTE))
+		.map(|i| i as u32)
+		.ok_or_else(|| anyhow!(ComputeError::NoComputeQueue))
+}
+
+/// The family `selection` asks for and its `timestampValidBits`. With `prefer_timestamps`
+/// a family that can't write timestamps gives way to one that can, if there is one.
+pub unsafe fn get_compute_queue_family_index(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+	selection: QueueSelection,
+	prefer_timestamps: bool,
+) -> Result<(u32, u32)> {
+	let index = match selection {
+		QueueSelection::First => get_first_compute_queue_family_index(instance, physical_device),
+		QueueSelection::Dedicated => {
+			get_dedicated_compute_queue_family_index(instance, physical_device)
+		}
+	}?;
+
+	let properties = instance.get_physical_device_queue_family_properties(physical_device);
+	let chosen = &properties[index as usize];
+	if !prefer_timestamps || HAS_TIMESTAMPS(chosen) {
+		return Ok((index, chosen.timestamp_valid_bits));
+	}
+
+	let with_timestamps = |p: &vk::QueueFamilyProperties| HAS_COMPUTE(p) && HAS_TIMESTAMPS(p);
+	let dedicated_with_timestamps = |p: &vk::QueueFamilyProperties| {
+		selection == QueueSelection::Dedicated && IS_DEDICATED_COMPUTE(p) && HAS_TIMESTAMPS(p)
+	};
+	match properties
+		.iter()
+		.position(dedicated_with_timestamps)
+		.or_else(|| properties.iter().position(with_timestamps))
+	{
+		Some(other) => {
+			info!(
+				"using compute queue family {} rather than {}, which has no timestamps",
+				other, index
+			);
+			Ok((other as u32, properties[other].timestamp_valid_bits))
+		}
+		None => {
+			warn!("no compute queue family supports timestamps, GPU timing is unavailable");
+			Ok((index, 0))
+		}
+	}
+}
+
+/// A transfer-only family for staging uploads, if the device has one.
+pub unsafe fn get_transfer_queue_family_index(
+	instance: &Instance,
+	physical_device: vk::PhysicalDevice,
+) -> Option<u32> {
+	instance
+		.get_physical_device_queue_family_properties(physical_device)
+		.iter()
+		.position(IS_DEDICATED_TRANSFER)
+		.map(|i| i as u32)
+}
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// The five words every SPIR-V module starts with.
+#[derive(Clone, Copy, Debug)]
+pub struct SpirvHeader {
+	pub major: u8,
+	pub minor: u8,
+	/// the tool that wrote the module, as numbered in the SPIR-V registry
+	pub generator: u32,
+	/// every id in the module is below this
+	pub bound: u32,
+}
+
+/// Checks `bytecode` is whole words starting with a SPIR-V header, without a device.
+pub fn parse_spirv_header(bytecode: &[u8]) -> Result<SpirvHeader> {
+	if !bytecode.len().is_multiple_of(4) {
+		return Err(anyhow!(
+			"{} bytes isn't a whole number of 32-bit words",
+			bytecode.len()
+		));
+	}
+	let words = bytecode
+		.chunks_exact(4)
+		.take(5)
+		.map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+		.collect::<Vec<_>>();
+	if words.len() < 5 {
+		return Err(anyhow!(
+			"{} words is too short for the five-word header",
+			words.len()
+		));
+	}
+	if words[0] == SPIRV_MAGIC.swap_bytes() {
+		return Err(anyhow!("the module was written in the other byte order"));
+	}
+	if words[0] != SPIRV_MAGIC {
+		return Err(anyhow!("it doesn't start with the SPIR-V magic number"));
+	}
+
+	let major = (words[1] >> 16) as u8;
+	let minor = (words[1] >> 8) as u8;
+	if words[1] & 0xff00_00ff != 0 || major != 1 || minor > 6 {
+		return Err(anyhow!("unknown SPIR-V version word {:#010x}", words[1]));
+	}
+	if words[3] == 0 {
+		return Err(anyhow!("the id bound is 0"));
+	}
+	if words[4] != 0 {
+		return Err(anyhow!("the reserved schema word is {}, not 0", words[4]));
+	}
+	Ok(SpirvHeader {
+		major,
+		minor,
+		generator: words[2],
+		bound: words[3],
+	})
+}
+
 pub unsafe fn create_shader_module(device: &Device, bytecode: &[u8]) -> Result<vk::ShaderModule> {
-	let bytecode = Vec::<u8>::from(bytecode);
-	let (prefix, code, suffix) = bytecode.align_to::<u32>();
-	if !prefix.is_empty() || !suffix.is_empty() {
-		return Err(anyhow!("Shader bytecode is not properly aligned."));
+	// only copied when the bytes don't already start on a word boundary, which
+	// include_bytes! output usually does
+	let code: Cow<[u32]> = match bytecode.align_to::<u32>() {
+		([], code, []) => Cow::Borrowed(code),
+		_ if bytecode.len().is_multiple_of(4) => Cow::Owned(
+			bytecode
+				.chunks_exact(4)
+				.map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+				.collect(),
+		),
+		_ => {
+			return Err(anyhow!(ComputeError::ShaderModule(
+				"Shader bytecode is not a whole number of 32-bit words."
+			)))
+		}
+	};
+	if code.first() != Some(&SPIRV_MAGIC) {
+		return Err(anyhow!(ComputeError::ShaderModule(
+			"Shader bytecode doesn't start with the SPIR-V magic number."
+		)));
 	}
 
 	let info = vk::ShaderModuleCreateInfo::builder()
 		.code_size(bytecode.len())
-		.code(code);
+		.code(&code);
 
-	Ok(device.create_shader_module(&info, None)?)
+	Ok(device
+		.create_shader_module(&info, None)
+		.map_err(ComputeError::Vulkan)?)
 }
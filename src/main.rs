@@ -1,537 +1,1215 @@
 #![allow(dead_code, unused_variables)]
 
-mod lib;
-
-use std::collections::HashSet;
-use std::mem::size_of;
-use std::ptr::copy_nonoverlapping as memcpy;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
-use lib::{
-	create_shader_module, get_best_memory_type_index, get_config,
-	get_first_compute_queue_family_index, pick_physical_device, Config, DeviceConfig,
+use clap::builder::BoolishValueParser;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use log::{debug, info, warn, LevelFilter};
+use owo_colors::{AnsiColors, OwoColorize, Stream};
+use serde::Serialize;
+use vk_compute::app::{create_entry, create_instance, default_validation, AppBuilder};
+use vk_compute::compare::compare;
+use vk_compute::context::ComputeContext;
+use vk_compute::host::{self, doubled, run_compute_or_host, Backend};
+use vk_compute::info::{get_device_info, get_device_profile, DeviceInfo};
+use vk_compute::input::{generate_input, read_raw_floats};
+use vk_compute::output::write_output;
+use vk_compute::reflect::reflect_layout;
+use vk_compute::{
+	disassemble, get_config, get_device_uuid, load_shader, parse_spirv_header,
+	pick_physical_device, run_spirv_val, ApplicationConfig, BufferUsage, ComputeConfig, Config,
+	DeviceConfig, DevicePreference, FeatureConfig, SPIRV_VAL_ENV,
 };
-use owo_colors::{AnsiColors, OwoColorize};
-use vulkanalia::loader::{LibloadingLoader, LIBRARY};
 use vulkanalia::prelude::v1_1::*;
 
-const VALIDATION_ENABLED: bool = cfg!(debug_assertions);
+#[derive(Parser)]
+#[command(about = "Runs compute shaders over a buffer of floats with Vulkan")]
+struct Cli {
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	#[arg(long, global = true, default_value = "config.toml")]
+	config: PathBuf,
+
+	/// merge this [profiles.<name>] table over the rest of the config, instead of
+	/// the file's default_profile
+	#[arg(long, global = true)]
+	profile: Option<String>,
+
+	#[arg(long, global = true)]
+	no_color: bool,
+
+	/// set up the device, shaders and pipelines for run or bench, time each stage and
+	/// tear down again without dispatching anything
+	#[arg(long, global = true)]
+	dry_run: bool,
+
+	/// run the bundled shader's arithmetic on the host instead of through Vulkan
+	#[arg(long, global = true)]
+	cpu: bool,
+
+	/// run on the host, as with --cpu, if Vulkan can't be set up
+	#[arg(long, global = true, conflicts_with = "cpu")]
+	allow_cpu_fallback: bool,
+
+	/// print the SPIR-V disassembly of the shaders run or bench would use, through
+	/// spirv-dis (or SPIRV_DIS), instead of running them
+	#[arg(long, global = true)]
+	disasm: bool,
+
+	/// seed random input with this, overriding the config's seed; without either the
+	/// seed comes from the clock and is logged so the run can be reproduced
+	#[arg(long, global = true)]
+	seed: Option<u64>,
+
+	/// put each element N positions after the last in the input and output buffers, for
+	/// a shader reading one component of interleaved data; overrides element_stride
+	#[arg(
+		long,
+		global = true,
+		value_name = "N",
+		value_parser = clap::value_parser!(u32).range(1..),
+	)]
+	element_stride: Option<u32>,
+
+	/// which position within each --element-stride holds the element; overrides
+	/// element_offset
+	#[arg(long, global = true, value_name = "N")]
+	element_offset: Option<u32>,
+
+	/// print how long each setup stage of run or bench took
+	#[arg(long, global = true)]
+	timings: bool,
+
+	/// run N times on one context, each with fresh input: random seeds count up, and
+	/// from_file inputs and output_path get the run's index, as in input.0.bin
+	#[arg(
+		long,
+		global = true,
+		value_name = "N",
+		default_value_t = 1,
+		value_parser = clap::value_parser!(u32).range(1..),
+		conflicts_with_all = ["cpu", "allow_cpu_fallback"],
+	)]
+	repeat: u32,
+
+	/// run this instead of the bundled shader: a name from src/shaders, a SPIR-V path,
+	/// or - to read SPIR-V from stdin
+	#[arg(long, global = true)]
+	shader: Option<String>,
+
+	/// compile this GLSL compute shader with glslangValidator (or GLSLANG_VALIDATOR) and
+	/// run it, then recompile and rerun it on the same device whenever it changes, until
+	/// interrupted
+	#[cfg(feature = "watch")]
+	#[arg(
+		long,
+		global = true,
+		value_name = "SOURCE",
+		conflicts_with_all = ["shader", "cpu", "allow_cpu_fallback", "dry_run", "disasm", "repeat", "all_devices"],
+	)]
+	watch: Option<PathBuf>,
+
+	/// run on every device with a compute queue in turn, each set up afresh, then
+	/// compare their timings and result checksums; devices that fail are noted and
+	/// the rest still run
+	#[arg(
+		long,
+		global = true,
+		conflicts_with_all = ["cpu", "allow_cpu_fallback", "dry_run", "disasm", "repeat", "json_results"],
+	)]
+	all_devices: bool,
+
+	/// print each run as a JSON object on stdout: the device, element count, timings,
+	/// whether the values matched and the results, summarized when there are many
+	#[arg(long, global = true)]
+	json_results: bool,
+
+	/// put every result in --json-results' output, not just the first and last few
+	#[arg(long, global = true, requires = "json_results")]
+	json_full: bool,
+
+	/// print a checksum of all the results, the same on any platform, to tell whether
+	/// two runs came out identical without diffing their output
+	#[arg(long, global = true)]
+	checksum: bool,
+
+	/// how list and info print devices
+	#[arg(long, global = true, value_enum, default_value_t = ReportFormat::Table)]
+	format: ReportFormat,
+
+	/// more output for each -v, less for each -q; RUST_LOG still wins
+	#[arg(short, long, global = true, action = ArgAction::Count)]
+	verbose: u8,
+
+	#[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "verbose")]
+	quiet: u8,
+
+	/// load the validation layer (on or off); defaults to VK_COMPUTE_VALIDATION, then to
+	/// whether this is a debug build
+	#[arg(
+		long,
+		global = true,
+		value_name = "on|off",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "on",
+		value_parser = BoolishValueParser::new(),
+		hide_possible_values = true,
+	)]
+	validation: Option<bool>,
+
+	/// catch out-of-bounds storage buffer access from inside the shader
+	#[arg(long, global = true)]
+	gpu_validation: bool,
+
+	#[arg(long, global = true)]
+	best_practices: bool,
+
+	/// load the validation layer for its best-practices advice alone, with the usual
+	/// checks and their overhead switched off
+	#[arg(
+		long,
+		global = true,
+		conflicts_with_all = ["validation", "gpu_validation", "sync_validation"],
+	)]
+	best_practices_only: bool,
+
+	#[arg(long, global = true)]
+	sync_validation: bool,
+}
 
-const VK_KHR_PORTABILITY_SUBSET_STR: &str = "VK_KHR_portability_subset";
-const QUARTER_SECOND_IN_NANOS : u64 = 250000000;
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+	Table,
+	Json,
+}
+
+/// How many values --json-results keeps from each end of the results without
+/// --json-full.
+const JSON_SUMMARY_EDGE: usize = 8;
+
+/// What a run reports besides its values, for --json-results.
+struct JsonRun {
+	full: bool,
+	device: Option<String>,
+	backend: Backend,
+	/// host-side time to upload, dispatch and read back
+	dispatch: Option<Duration>,
+	pass_durations: Vec<Duration>,
+}
 
-const VALIDATION_LAYER: vk::ExtensionName =
-	vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
-const VK_KHR_PORTABILITY_SUBSET: vk::ExtensionName =
-	vk::ExtensionName::from_bytes(VK_KHR_PORTABILITY_SUBSET_STR.as_bytes());
+#[derive(Serialize)]
+struct JsonResults {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	device: Option<String>,
+	backend: String,
+	element_count: usize,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	dispatch_ms: Option<f64>,
+	/// each pass's device time, with pass_timings
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pass_ms: Vec<f64>,
+	/// only known for the bundled shader
+	#[serde(skip_serializing_if = "Option::is_none")]
+	all_values_match: Option<bool>,
+	/// with --checksum
+	#[serde(skip_serializing_if = "Option::is_none")]
+	checksum: Option<String>,
+	results: JsonValues,
+}
 
-const NUM_FLOATS: usize = 16384;
-const NUM_BUFFERS: usize = 2;
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum JsonValues {
+	All(Vec<f32>),
+	/// the first and last few values and a [`checksum`] of all of them
+	Summary {
+		first: Vec<f32>,
+		last: Vec<f32>,
+		checksum: String,
+	},
+}
 
-unsafe fn create_instance(entry: &Entry) -> Result<Instance> {
-	let application_info = vk::ApplicationInfo::builder()
-		.application_name(b"VKFromFileComputeSample\0")
-		.application_version(vk::make_version(1, 0, 0))
-		.engine_name(b"No Engine\0")
-		.engine_version(vk::make_version(1, 0, 0))
-		.api_version(vk::make_version(1, 1, 0))
-		.build();
+impl JsonValues {
+	fn new(results: &[f32], full: bool) -> Self {
+		if full || results.len() <= 2 * JSON_SUMMARY_EDGE {
+			return JsonValues::All(results.to_vec());
+		}
+		JsonValues::Summary {
+			first: results[..JSON_SUMMARY_EDGE].to_vec(),
+			last: results[results.len() - JSON_SUMMARY_EDGE..].to_vec(),
+			checksum: checksum(results),
+		}
+	}
+}
 
-	let available_layers = entry
-		.enumerate_instance_layer_properties()?
+/// An FNV-1a hash of all of `values`' bits, in hex, to tell results apart at a glance.
+/// It hashes the little-endian bytes, so a big-endian host gets the same one.
+fn checksum(values: &[f32]) -> String {
+	let hash = values
 		.iter()
-		.map(|l| l.layer_name)
-		.collect::<HashSet<_>>();
+		.flat_map(|value| value.to_le_bytes())
+		.fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+			(hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+		});
+	format!("{:016x}", hash)
+}
 
-	if VALIDATION_ENABLED && !available_layers.contains(&VALIDATION_LAYER) {
-		return Err(anyhow!("Validation layer requested but not supported."));
+#[derive(Subcommand)]
+enum Command {
+	/// run the configured shader(s) once and report the results
+	Run,
+	/// list every device the loader can see
+	List,
+	/// print the selected device's heaps, queues, limits and features
+	Info,
+	/// dispatch the configured shader(s) repeatedly and report timings
+	Bench {
+		#[arg(default_value_t = 100)]
+		iterations: u32,
+	},
+	/// compare two raw f32 result files, failing if they differ by more than the tolerance
+	Diff {
+		expected: PathBuf,
+		actual: PathBuf,
+		/// largest absolute difference still counted as a match
+		#[arg(long, default_value_t = 0.0)]
+		tolerance: f32,
+		/// how many of the most different elements to list
+		#[arg(long, default_value_t = 10)]
+		worst: usize,
+	},
+	/// check a SPIR-V file's header, that it parses and, with spirv-val installed, that it
+	/// validates, all without a device
+	ValidateShader { path: PathBuf },
+}
+
+impl Cli {
+	fn validation_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+		let mut features = Vec::new();
+		if self.gpu_validation {
+			features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+			features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+		}
+		if self.best_practices || self.best_practices_only {
+			features.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+		}
+		if self.sync_validation {
+			features.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+		}
+		features
 	}
 
-	log_validation();
+	fn disabled_validation_features(&self) -> Vec<vk::ValidationFeatureDisableEXT> {
+		if !self.best_practices_only {
+			return Vec::new();
+		}
+		vec![
+			vk::ValidationFeatureDisableEXT::SHADERS,
+			vk::ValidationFeatureDisableEXT::THREAD_SAFETY,
+			vk::ValidationFeatureDisableEXT::API_PARAMETERS,
+			vk::ValidationFeatureDisableEXT::OBJECT_LIFETIMES,
+			vk::ValidationFeatureDisableEXT::CORE_CHECKS,
+		]
+	}
 
-	let layers = if VALIDATION_ENABLED {
-		vec![VALIDATION_LAYER.as_ptr()]
-	} else {
-		Vec::new()
-	};
+	fn log_level(&self) -> LevelFilter {
+		const LEVELS: [LevelFilter; 6] = [
+			LevelFilter::Off,
+			LevelFilter::Error,
+			LevelFilter::Warn,
+			LevelFilter::Info,
+			LevelFilter::Debug,
+			LevelFilter::Trace,
+		];
+		let default = LEVELS.iter().position(|l| *l == LevelFilter::Info).unwrap();
+		let index = (default + self.verbose as usize).saturating_sub(self.quiet as usize);
+		LEVELS[index.min(LEVELS.len() - 1)]
+	}
+}
 
-	let instance_create_info = vk::InstanceCreateInfo::builder()
-		.application_info(&application_info)
-		.enabled_layer_names(&layers)
-		.build();
-	Ok(entry.create_instance(&instance_create_info, None)?)
-}
-
-#[derive(Clone, Debug)]
-struct App {
-	entry: Entry,
-	instance: Instance,
-	physical_device: vk::PhysicalDevice,
-	logical_device: Device,
-	queue_index: u32,
-	memory_index: u32,
-	memory: vk::DeviceMemory,
-	compute_shader: vk::ShaderModule,
-	done_fence : vk::Fence
-}
-
-impl App {
-	unsafe fn create(config: &DeviceConfig) -> Result<App> {
-		let loader = LibloadingLoader::new(LIBRARY)?;
-		let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
-		let instance = create_instance(&entry)?;
-		let physical_device = pick_physical_device(&instance, &config)?;
-
-		let compute_queue_index = get_first_compute_queue_family_index(&instance, physical_device)?;
-		let queue_priorities = &[1.0];
-		let queue_infos = &[vk::DeviceQueueCreateInfo::builder()
-			.queue_family_index(compute_queue_index)
-			.queue_priorities(queue_priorities)
-			.build()];
-
-		let layers = if VALIDATION_ENABLED {
-			vec![VALIDATION_LAYER.as_ptr()]
-		} else {
-			Vec::new()
-		};
+unsafe fn list_devices(application: &ApplicationConfig, format: ReportFormat) -> Result<()> {
+	let entry = create_entry()?;
+	let instance = create_instance(&entry, application, false, &[], &[])?;
+	let devices = instance.enumerate_physical_devices().map(|devices| {
+		devices
+			.into_iter()
+			.map(|physical_device| get_device_info(&instance, physical_device))
+			.collect::<Vec<_>>()
+	});
+	instance.destroy_instance(None);
+
+	match format {
+		ReportFormat::Table => devices?.iter().for_each(print_device_table),
+		ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&devices?)?),
+	}
+	Ok(())
+}
 
-		let does_have_portability_subset_extension =
-			has_portability_subset_extension(&instance, physical_device)?;
-		let extensions = if does_have_portability_subset_extension {
-			vec![VK_KHR_PORTABILITY_SUBSET.as_ptr()]
+fn print_device_table(device: &DeviceInfo) {
+	println!(
+		"{} (vendor_id {:x}, device_id {:x}, {}){}{}",
+		device.name,
+		device.vendor_id,
+		device.device_id,
+		device.device_type,
+		if device.device_type == "CPU" {
+			", software"
 		} else {
-			Vec::new()
-		};
-
-		let device_create_info_partial = vk::DeviceCreateInfo::builder()
-			.queue_create_infos(queue_infos)
-			.enabled_layer_names(&layers)
-			.enabled_extension_names(&extensions);
-
-		let device_create_info = if does_have_portability_subset_extension {
-			//required for shim'd Vulkan spec implementations, like MoltenVK
-			let mut more_features = vk::PhysicalDeviceFeatures2::builder().build();
-			instance.get_physical_device_features2(physical_device, &mut more_features);
-			device_create_info_partial
-				.push_next(&mut more_features)
-				.build()
+			""
+		},
+		if device.has_compute_queue {
+			""
 		} else {
-			let features = instance
-				.get_physical_device_features(physical_device);
-			device_create_info_partial
-				.enabled_features(&features)
-				.build()
+			", no compute queue"
+		}
+	);
+	if device.portability_subset {
+		println!("  portability subset only (VK_KHR_portability_subset)");
+	}
+	println!(
+		"  api {}, driver {:#x}",
+		device.api_version, device.driver_version
+	);
+	for (i, heap) in device.memory_heaps.iter().enumerate() {
+		let budget = match (heap.budget, heap.usage) {
+			(Some(budget), Some(usage)) => format!(
+				" ({} MiB budget, {} MiB used)",
+				budget / (1024 * 1024),
+				usage / (1024 * 1024)
+			),
+			_ => String::new(),
 		};
+		println!(
+			"  heap {}: {} MiB{} {}",
+			i,
+			heap.size / (1024 * 1024),
+			budget,
+			heap.flags.join(" | ")
+		);
+	}
+	for (i, family) in device.queue_families.iter().enumerate() {
+		let implied_transfer =
+			family.transfer && !family.flags.iter().any(|flag| flag == "TRANSFER");
+		println!(
+			"  queue family {}: {} x {}{}, {} timestamp bits",
+			i,
+			family.queue_count,
+			family.flags.join(" | "),
+			if implied_transfer {
+				" (transfer implied)"
+			} else {
+				""
+			},
+			family.timestamp_valid_bits
+		);
+	}
+}
 
-		let logical_device = instance.create_device(physical_device, &device_create_info, None)?;
-
-		let shader_binary = std::include_bytes!("../compute.spv");
-		let compute_shader = create_shader_module(&logical_device, shader_binary)?;
-
-		let memory_propertes = instance.get_physical_device_memory_properties(physical_device);
-		let desired_size = (NUM_BUFFERS * NUM_FLOATS * size_of::<f32>()) as vk::DeviceSize;
+/// One `name: value` line per field of a serialized struct, indented under `title`.
+fn print_fields(title: &str, value: &impl Serialize) -> Result<()> {
+	println!("  {}:", title);
+	if let serde_json::Value::Object(fields) = serde_json::to_value(value)? {
+		for (name, value) in fields {
+			println!("    {}: {}", name, value);
+		}
+	}
+	Ok(())
+}
 
-		let memory_index: u32 = get_best_memory_type_index(
-			&memory_propertes,
-			vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-			desired_size as usize,
-		)?;
+unsafe fn print_device_info(
+	config: &DeviceConfig,
+	application: &ApplicationConfig,
+	validation: bool,
+	format: ReportFormat,
+) -> Result<()> {
+	let entry = create_entry()?;
+	let instance = create_instance(&entry, application, validation, &[], &[])?;
+	let profile = config
+		.device_preference()
+		.and_then(|preference| pick_physical_device(&instance, preference))
+		.and_then(|physical_device| get_device_profile(&instance, physical_device));
+	instance.destroy_instance(None);
+
+	let profile = profile?;
+	match format {
+		ReportFormat::Table => {
+			print_device_table(&profile.device);
+			print_fields("limits", &profile.limits)?;
+			print_fields("features", &profile.features)?;
+			if let Some(subgroup) = &profile.subgroup {
+				print_fields("subgroup", subgroup)?;
+			}
+			if let Some(portability) = &profile.portability {
+				print_fields("portability subset", portability)?;
+			}
+		}
+		ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&profile)?),
+	}
+	Ok(())
+}
 
-		let memory_allocate_info = vk::MemoryAllocateInfo::builder()
-			.allocation_size(desired_size)
-			.memory_type_index(memory_index)
-			.build();
+/// Logs at `level`, info by default so the usual chatter still shows, unless RUST_LOG
+/// says otherwise.
+fn init_logging(use_color: bool, level: LevelFilter) {
+	let mut logger = pretty_env_logger::formatted_builder();
+	match std::env::var("RUST_LOG") {
+		Ok(filters) => logger.parse_filters(&filters),
+		Err(_) => logger.filter_level(level),
+	};
+	if !use_color {
+		logger.parse_write_style("never");
+	}
+	logger.init();
+}
 
-		let memory = logical_device.allocate_memory(&memory_allocate_info, None)?;
+/// Prints each span's timing as it closes. This is its own subscriber rather than a
+/// bridge from `log`, which pretty_env_logger already owns.
+#[cfg(feature = "tracing")]
+fn init_tracing(use_color: bool) -> Result<()> {
+	let subscriber = tracing_subscriber::fmt()
+		.with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+		.with_ansi(use_color)
+		.with_writer(std::io::stderr)
+		.finish();
+	tracing::subscriber::set_global_default(subscriber)?;
+	Ok(())
+}
 
-		let queue_index: u32 = compute_queue_index;
+fn main() -> Result<()> {
+	let cli = Cli::parse();
+
+	// owo-colors already skips non-terminals and honours NO_COLOR on its own,
+	// the logger needs telling
+	let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+	let use_color = !no_color_env && !cli.no_color;
+	if !use_color {
+		owo_colors::set_override(false);
+		vk_compute::progress::set_enabled(false);
+	}
+	init_logging(use_color, cli.log_level());
+	#[cfg(feature = "tracing")]
+	init_tracing(use_color)?;
+
+	// Some(full) with --json-results
+	let json = cli.json_results.then_some(cli.json_full);
+	if json.is_some() && !matches!(cli.command, None | Some(Command::Run)) {
+		return Err(anyhow!("--json-results only works with run"));
+	}
+	if cli.checksum && !matches!(cli.command, None | Some(Command::Run)) {
+		return Err(anyhow!("--checksum only works with run"));
+	}
 
-		let fence_create = vk::FenceCreateInfo::builder()
-			.flags(vk::FenceCreateFlags::SIGNALED)
-			.build();
+	if let Some(Command::List) = cli.command {
+		return unsafe { list_devices(&ApplicationConfig::default(), cli.format) };
+	}
+	if let Some(Command::Diff {
+		expected,
+		actual,
+		tolerance,
+		worst,
+	}) = &cli.command
+	{
+		return diff(expected, actual, *tolerance, *worst);
+	}
+	if let Some(Command::ValidateShader { path }) = &cli.command {
+		return validate_shader(path);
+	}
 
-		let done_fence = logical_device.create_fence(&fence_create, None)?;
+	let Config {
+		device: device_config,
+		features: feature_config,
+		compute: mut compute_config,
+		application: application_config,
+	} = get_config(&cli.config, cli.profile.as_deref())?;
+
+	match compute_config.input_pattern.settle_seed(cli.seed) {
+		Some(seed) => info!(
+			"random input seed {}, pass --seed {} to repeat it",
+			seed, seed
+		),
+		None if cli.seed.is_some() => warn!("--seed only applies to random input patterns"),
+		None => {}
+	}
+	if let Some(stride) = cli.element_stride {
+		compute_config.element_stride = stride;
+	}
+	if let Some(offset) = cli.element_offset {
+		compute_config.element_offset = offset;
+	}
 
-		Ok(Self {
-			entry,
-			instance,
-			physical_device,
-			logical_device,
-			queue_index,
-			memory_index,
-			memory,
-			compute_shader,
-			done_fence
-		})
+	let validation = cli.best_practices_only || cli.validation.unwrap_or_else(default_validation);
+	let validation_features = cli.validation_features();
+	let disabled_validation_features = cli.disabled_validation_features();
+
+	let builder = || -> Result<AppBuilder> {
+		let builder = app_builder(
+			&device_config,
+			&feature_config,
+			&application_config,
+			validation,
+			&validation_features,
+			&disabled_validation_features,
+		)?;
+		let builder = with_compute_layout(builder, &compute_config);
+		match &cli.shader {
+			Some(shader) => Ok(builder.shader_bytes(load_shader(shader)?)),
+			None => Ok(builder),
+		}
+	};
+	// only the bundled shader's answer is known
+	let verify = cli.shader.is_none() && compute_config.passes.is_empty();
+	if (cli.cpu || cli.allow_cpu_fallback) && !verify {
+		return Err(anyhow!(
+			"the host backend only runs the bundled shader, not --shader or passes"
+		));
 	}
 
-	pub unsafe fn populate_buffer(&mut self) -> Result<()> {
-		let mut floats: Vec<f32> = Vec::with_capacity(NUM_FLOATS);
+	let command = cli.command.unwrap_or(Command::Run);
+	if cli.all_devices {
+		if !matches!(command, Command::Run) {
+			return Err(anyhow!("--all-devices only works with run"));
+		}
+		return run_on_all_devices(builder, &application_config, &compute_config, verify);
+	}
+	if cli.disasm && matches!(command, Command::Run | Command::Bench { .. }) {
+		return print_disassembly(&builder()?, &compute_config);
+	}
 
-		for item in 0..NUM_FLOATS {
-			floats.push((item as f32) * 0.5);
+	#[cfg(feature = "watch")]
+	if let Some(source) = &cli.watch {
+		if !matches!(command, Command::Run) {
+			return Err(anyhow!("--watch only works with run"));
 		}
+		return watch(builder()?, &compute_config, source, cli.checksum);
+	}
 
-		let shader_read_buffer_size = (NUM_FLOATS * size_of::<f32>()) as vk::DeviceSize;
-		let mapped = self.logical_device.map_memory(
-			self.memory,
-			0,
-			shader_read_buffer_size,
-			vk::MemoryMapFlags::empty(),
-		)?;
+	match command {
+		Command::Run | Command::Bench { .. } if cli.dry_run => dry_run(builder()?, &compute_config),
+		Command::Run if cli.cpu => run_on_host(builder()?, &compute_config, json, cli.checksum),
+		Command::Run if cli.allow_cpu_fallback => {
+			run_with_fallback(builder()?, &compute_config, json, cli.checksum)
+		}
+		Command::Run => run(
+			builder()?,
+			&compute_config,
+			verify,
+			cli.timings,
+			cli.repeat as usize,
+			json,
+			cli.checksum,
+		),
+		Command::List => unreachable!("listed above, before loading the config"),
+		Command::Diff { .. } => unreachable!("compared above, before loading the config"),
+		Command::ValidateShader { .. } => {
+			unreachable!("validated above, before loading the config")
+		}
+		Command::Info => unsafe {
+			print_device_info(&device_config, &application_config, validation, cli.format)
+		},
+		Command::Bench { iterations } => {
+			bench(builder()?, &compute_config, iterations, cli.timings)
+		}
+	}
+}
 
-		memcpy(floats.as_ptr(), mapped.cast(), floats.len());
-
-		self.logical_device.unmap_memory(self.memory);
-
-		Ok(())
-	}
-
-	pub unsafe fn bind_buffer_layout(
-		&mut self,
-	) -> Result<(vk::Buffer, vk::Buffer, vk::DescriptorSetLayout)> {
-		let size_and_offset = (NUM_FLOATS * size_of::<f32>()) as vk::DeviceSize;
-
-		let buffer_info = vk::BufferCreateInfo::builder()
-			.size(size_and_offset)
-			.usage(vk::BufferUsageFlags::STORAGE_BUFFER)
-			.sharing_mode(vk::SharingMode::EXCLUSIVE)
-			.build();
-
-		let in_buffer = self.logical_device.create_buffer(&buffer_info, None)?;
-		self.logical_device
-			.bind_buffer_memory(in_buffer, self.memory, 0)?;
-
-		let out_buffer = self.logical_device.create_buffer(&buffer_info, None)?;
-		self.logical_device
-			.bind_buffer_memory(out_buffer, self.memory, size_and_offset)?;
-
-		let bindings: Vec<vk::DescriptorSetLayoutBinding> = vec![
-			vk::DescriptorSetLayoutBinding::builder()
-				.binding(0)
-				.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-				.descriptor_count(1)
-				.stage_flags(vk::ShaderStageFlags::COMPUTE)
-				.build(),
-			vk::DescriptorSetLayoutBinding::builder()
-				.binding(1)
-				.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-				.descriptor_count(1)
-				.stage_flags(vk::ShaderStageFlags::COMPUTE)
-				.build(),
-		];
+fn app_builder(
+	device_config: &DeviceConfig,
+	feature_config: &FeatureConfig,
+	application_config: &ApplicationConfig,
+	validation: bool,
+	validation_features: &[vk::ValidationFeatureEnableEXT],
+	disabled_validation_features: &[vk::ValidationFeatureDisableEXT],
+) -> Result<AppBuilder> {
+	let [major, minor, patch] = application_config.version;
+	Ok(AppBuilder::new()
+		.application_name(application_config.name.as_str())
+		.application_version(major, minor, patch)
+		.device_preference(device_config.device_preference()?)
+		.queue_selection(device_config.queue_selection)
+		.prefer_timestamps(device_config.prefer_timestamps)
+		.separate_transfer_queue(device_config.separate_transfer_queue)
+		.buffer_sharing(device_config.buffer_sharing)
+		.queue_priority(device_config.queue_priority)
+		.features(feature_config.clone())
+		.validation(validation)
+		.validation_features(validation_features)
+		.disabled_validation_features(disabled_validation_features))
+}
 
-		let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
-		let layout = self
-			.logical_device
-			.create_descriptor_set_layout(&info, None)?;
+/// The parts of `[compute]` that decide how big the buffers are and how they're dispatched.
+fn with_compute_layout(builder: AppBuilder, compute_config: &ComputeConfig) -> AppBuilder {
+	let mut builder = builder
+		.element_type(compute_config.element_type)
+		.element_stride(compute_config.element_stride, compute_config.element_offset)
+		.local_size(compute_config.local_size);
+	builder = builder
+		.host_cached(compute_config.host_cached)
+		.buffer_usage(BufferUsage::combined(&compute_config.buffer_usage));
+	if let Some(flags) = compute_config.memory_property_flags() {
+		builder = builder.memory_flags(flags);
+	}
+	if let Some(size) = compute_config.subgroup_size {
+		builder = builder.subgroup_size(size);
+	}
+	match compute_config.dimensions {
+		Some(dimensions) => builder.dimensions(dimensions),
+		None => builder,
+	}
+}
 
-		Ok((in_buffer, out_buffer, layout))
+#[rustfmt::skip]
+fn run(
+	builder: AppBuilder,
+	compute_config: &ComputeConfig,
+	verify: bool,
+	timings: bool,
+	repeat: usize,
+	json: Option<bool>,
+	print_checksum: bool,
+) -> Result<()> {
+	// the whole input, even when the context only takes it a chunk at a time
+	let element_count = builder.get_element_count();
+	let mut context = unsafe { new_context(builder, compute_config)? };
+	let device = json.map(|_| unsafe { get_device_info(&context.app.instance, context.app.physical_device).name });
+	if timings {
+		print_setup_timings(&context);
 	}
+	let app = &context.app;
+	info!("found compute index {} and memory index {}", 
+		(app.queue_index).if_supports_color(Stream::Stderr, |t| t.green()),
+		(app.memory_index).if_supports_color(Stream::Stderr, |t| t.green()));
+	if let Some(transfer_queue_index) = app.transfer_queue_index {
+		info!("found separate transfer index {}",
+			transfer_queue_index.if_supports_color(Stream::Stderr, |t| t.green()));
+	}
+	debug!("compute queue timestamps have {} valid bits", app.timestamp_valid_bits);
+	debug!("device limits: {:?}", app.limits);
 
-	pub unsafe fn create_descriptor_pool_and_set(
-		&self,
-		in_buffer: &vk::Buffer,
-		out_buffer: &vk::Buffer,
-		layout: &vk::DescriptorSetLayout,
-	) -> Result<(vk::DescriptorPool, vk::DescriptorSet)> {
-		let pool_size = vk::DescriptorPoolSize {
-			type_: vk::DescriptorType::STORAGE_BUFFER,
-			descriptor_count: NUM_BUFFERS as u32,
+	// stuff happens here
+	let started = Instant::now();
+	let mut dispatching = Duration::ZERO;
+	let outcome = (0..repeat).try_for_each(|index| {
+		let config = match repeat {
+			1 => Cow::Borrowed(compute_config),
+			_ => Cow::Owned(compute_config.nth_run(index)),
 		};
-		let pool_size_wrapper = &[pool_size];
-		let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
-			.max_sets(1)
-			.pool_sizes(pool_size_wrapper)
-			.build();
-		let descriptor_pool = self
-			.logical_device
-			.create_descriptor_pool(&pool_create_info, None)?;
-
-		let layout_wrapper = &[*layout];
-		let allocate_info = vk::DescriptorSetAllocateInfo::builder()
-			.descriptor_pool(descriptor_pool)
-			.set_layouts(layout_wrapper)
-			.build();
-
-		let descriptor_set = {
-			let mut descriptor_set_wrapper = self
-				.logical_device
-				.allocate_descriptor_sets(&allocate_info)?;
-			descriptor_set_wrapper.remove(0)
+		let input = generate_input(&config.input_pattern, element_count)?;
+		let dispatched = Instant::now();
+		let results = unsafe { context.dispatch_chunked(&input)? };
+		let dispatch = dispatched.elapsed();
+		dispatching += dispatch;
+		let json = json.map(|full| JsonRun {
+			full,
+			device: device.clone(),
+			backend: Backend::Vulkan,
+			dispatch: Some(dispatch),
+			pass_durations: context.pass_durations().to_vec(),
+		});
+		for (binding, values) in context.binding_readbacks() {
+			if let (Some(first), Some(last)) = (values.first(), values.last()) {
+				info!("binding {} read back; first value is {}, last is {}", binding, first, last);
+			}
+		}
+		report_results(&config, &input, &results, verify, json, print_checksum)
+	});
+	if repeat > 1 && outcome.is_ok() {
+		println!("{} runs in {:.3?}: {:.3?} uploading, dispatching and reading back, {:.3?} per run",
+			repeat, started.elapsed(), dispatching, dispatching / repeat as u32);
+	}
+
+	// even a lost device still has to be torn down, so stash any error until then, and
+	// report it over one from the teardown
+	let destroyed = unsafe { context.destroy() };
+	outcome?;
+	destroyed
+}
+
+/// How one device did for --all-devices.
+struct DeviceRun {
+	setup: Duration,
+	dispatch: Duration,
+	checksum: String,
+	/// whether the results are what the bundled shader should give
+	correct: bool,
+}
+
+/// [`run`] once on each device with a compute queue, then a table comparing them.
+/// Fails after the table if any device did, or got the wrong answer.
+fn run_on_all_devices(
+	builder: impl Fn() -> Result<AppBuilder>,
+	application_config: &ApplicationConfig,
+	compute_config: &ComputeConfig,
+	verify: bool,
+) -> Result<()> {
+	let devices = unsafe {
+		let entry = create_entry()?;
+		let instance = create_instance(&entry, application_config, false, &[], &[])?;
+		let devices = instance.enumerate_physical_devices().map(|devices| {
+			devices
+				.into_iter()
+				.map(|physical_device| {
+					(
+						get_device_info(&instance, physical_device),
+						get_device_uuid(&instance, physical_device),
+					)
+				})
+				.collect::<Vec<_>>()
+		});
+		instance.destroy_instance(None);
+		devices?
+	};
+	let input = generate_input(
+		&compute_config.input_pattern,
+		builder()?.get_element_count(),
+	)?;
+
+	let mut runs = Vec::new();
+	for (index, (device, uuid)) in devices.iter().enumerate() {
+		if !device.has_compute_queue {
+			continue;
+		}
+		// each run has an instance of its own, whose enumeration needn't be in the same
+		// order, so the device is picked out again by its UUID
+		let Some(uuid) = uuid else {
+			warn!(
+				"device {} {} is Vulkan 1.0 and has no UUID to pick it out by, skipping it",
+				index, device.name
+			);
+			continue;
 		};
+		let builder = builder()?.device_preference(DevicePreference::Uuid(*uuid));
+		let outcome = run_on_device(builder, compute_config, &input);
+		if let Err(e) = &outcome {
+			warn!("device {} {} failed: {:#}", index, device.name, e);
+		}
+		runs.push((index, &device.name, outcome));
+	}
+	if runs.is_empty() {
+		return Err(anyhow!("no device has a compute queue"));
+	}
 
-		let in_buffer_info = &[vk::DescriptorBufferInfo {
-			buffer: *in_buffer,
-			offset: 0,
-			range: vk::WHOLE_SIZE as vk::DeviceSize,
-		}];
-		let out_buffer_info = &[vk::DescriptorBufferInfo {
-			buffer: *out_buffer,
-			offset: 0,
-			range: vk::WHOLE_SIZE as vk::DeviceSize,
-		}];
-
-		let write_sets = &[
-			vk::WriteDescriptorSet::builder()
-				.dst_set(descriptor_set)
-				.dst_binding(0)
-				.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-				.buffer_info(in_buffer_info)
-				.build(),
-			vk::WriteDescriptorSet::builder()
-				.dst_set(descriptor_set)
-				.dst_binding(1)
-				.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-				.buffer_info(out_buffer_info)
-				.build(),
-		];
+	println!(
+		"{:<5} {:<32} {:>12} {:>12}  {:<16}",
+		"index", "device", "setup", "dispatch", "checksum"
+	);
+	for (index, name, outcome) in &runs {
+		match outcome {
+			Ok(run) => println!(
+				"{:<5} {:<32} {:>12.3?} {:>12.3?}  {:<16}{}",
+				index,
+				name,
+				run.setup,
+				run.dispatch,
+				run.checksum,
+				if verify && !run.correct {
+					"  wrong values"
+				} else {
+					""
+				}
+			),
+			Err(e) => println!("{:<5} {:<32} failed: {:#}", index, name, e),
+		}
+	}
 
-		self.logical_device
-			.update_descriptor_sets(write_sets, &[] as &[vk::CopyDescriptorSet]);
-
-		Ok((descriptor_pool, descriptor_set))
-	}
-
-	pub unsafe fn create_pipeine_with_layout(
-		&mut self,
-		descriptor_layout: &vk::DescriptorSetLayout,
-	) -> Result<(vk::Pipeline, vk::PipelineLayout)> {
-		let descriptor_layout_wrapped = &[*descriptor_layout];
-
-		let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
-			.set_layouts(descriptor_layout_wrapped)
-			.build();
-
-		let pipeline_layout = self
-			.logical_device
-			.create_pipeline_layout(&pipeline_layout_create_info, None)?;
-
-		let compute_pipeline_create_info = vk::ComputePipelineCreateInfo::builder()
-			.stage(
-				vk::PipelineShaderStageCreateInfo::builder()
-					.stage(vk::ShaderStageFlags::COMPUTE)
-					.module(self.compute_shader)
-					.name(b"main\0")
-					.build(),
-			)
-			.layout(pipeline_layout)
-			.build();
-
-		let (pipeline, _) = self.logical_device.create_compute_pipelines(
-			vk::PipelineCache::default(),
-			&[compute_pipeline_create_info],
-			None,
-		)?;
+	let mut checksums = runs
+		.iter()
+		.filter_map(|(_, _, outcome)| outcome.as_ref().ok())
+		.map(|run| run.checksum.as_str())
+		.collect::<Vec<_>>();
+	checksums.sort_unstable();
+	checksums.dedup();
+	if checksums.len() > 1 {
+		warn!("the devices' results differ");
+	}
 
-		Ok((pipeline, pipeline_layout))
-	}
-
-	pub unsafe fn create_command_pool_and_buffer(
-		&mut self,
-	) -> Result<(vk::CommandPool, vk::CommandBuffer)> {
-		let command_pool_create_info = vk::CommandPoolCreateInfo::builder()
-			.queue_family_index(self.queue_index)
-			.build();
-		let command_pool = self
-			.logical_device
-			.create_command_pool(&command_pool_create_info, None)?;
-
-		let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::builder()
-			.command_pool(command_pool)
-			.level(vk::CommandBufferLevel::PRIMARY)
-			.command_buffer_count(1)
-			.build();
-
-		let mut command_buffers = self
-			.logical_device
-			.allocate_command_buffers(&command_buffer_alloc_info)?;
-
-		Ok((command_pool, command_buffers.remove(0)))
-	}
-
-	pub unsafe fn record_commands_to_buffer(
-		&mut self,
-		command_buffer: &vk::CommandBuffer,
-		pipeline: &vk::Pipeline,
-		pipeline_layout: &vk::PipelineLayout,
-		descriptor_set: &vk::DescriptorSet,
-	) -> Result<(), vk::ErrorCode> {
-		let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
-			.flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
-			.build();
-
-		self.logical_device
-			.begin_command_buffer(*command_buffer, &command_buffer_begin_info)?;
-
-		self.logical_device.cmd_bind_pipeline(
-			*command_buffer,
-			vk::PipelineBindPoint::COMPUTE,
-			*pipeline,
-		);
+	let failed = runs
+		.iter()
+		.filter(|(_, _, outcome)| outcome.is_err())
+		.count();
+	let wrong = runs
+		.iter()
+		.filter(|(_, _, outcome)| matches!(outcome, Ok(run) if verify && !run.correct))
+		.count();
+	match (failed, wrong) {
+		(0, 0) => Ok(()),
+		(failed, 0) => Err(anyhow!("{} of {} devices failed", failed, runs.len())),
+		(failed, wrong) => Err(anyhow!(
+			"{} of {} devices failed and {} got the wrong values",
+			failed,
+			runs.len(),
+			wrong
+		)),
+	}
+}
 
-		self.logical_device.cmd_bind_descriptor_sets(
-			*command_buffer,
-			vk::PipelineBindPoint::COMPUTE,
-			*pipeline_layout,
-			0,
-			&[*descriptor_set],
-			&[],
-		);
+/// A context for [`ComputeContext::dispatch_chunked`], one that shrinks its buffers to
+/// fit with `chunk_on_oom`.
+unsafe fn new_context(
+	builder: AppBuilder,
+	compute_config: &ComputeConfig,
+) -> Result<ComputeContext> {
+	match compute_config.chunk_on_oom {
+		true => ComputeContext::new_chunked(builder, compute_config),
+		false => ComputeContext::new(builder, compute_config),
+	}
+}
 
-		self.logical_device
-			.cmd_dispatch(*command_buffer, NUM_FLOATS as u32, 1, 1);
-
-
-		self.logical_device.end_command_buffer(*command_buffer)
-	}
-
-	unsafe fn do_the_thing(&mut self, command_buffer: &vk::CommandBuffer)
-			 -> Result<Vec<f32>> {
-		let queue : vk::Queue = self.logical_device
-			.get_device_queue(self.queue_index, 0);
-		let command_buffer_wrapper = &[*command_buffer];
-
-		let submit_info = &[vk::SubmitInfo::builder()
-			.command_buffers(command_buffer_wrapper)
-			.build()];
-		
-		self.logical_device.reset_fences(&[self.done_fence])?;
-		self.logical_device.queue_submit(queue, submit_info, self.done_fence)?;
-		self.logical_device.wait_for_fences(&[self.done_fence], true,
-			QUARTER_SECOND_IN_NANOS)?;
-		
-		let buffer_size_and_offset = (NUM_FLOATS * size_of::<f32>()) as vk::DeviceSize;
-		let mapped = self.logical_device.map_memory(
-			self.memory,
-			buffer_size_and_offset,
-			buffer_size_and_offset,
-			vk::MemoryMapFlags::empty(),
-		)?;
+/// Sets up on the device `builder` picks, dispatches `input` once and tears down.
+fn run_on_device(
+	builder: AppBuilder,
+	compute_config: &ComputeConfig,
+	input: &[f32],
+) -> Result<DeviceRun> {
+	let started = Instant::now();
+	let mut context = unsafe { new_context(builder, compute_config)? };
+	let setup = started.elapsed();
+	let dispatched = Instant::now();
+	let outcome = unsafe { context.dispatch_chunked(input) };
+	let dispatch = dispatched.elapsed();
+	let destroyed = unsafe { context.destroy() };
+
+	let results = outcome?;
+	destroyed?;
+	Ok(DeviceRun {
+		setup,
+		dispatch,
+		checksum: checksum(&results),
+		correct: results
+			.iter()
+			.zip(input)
+			.all(|(result, value)| *result == 2.0 * value),
+	})
+}
 
-		let mut floats: Vec<f32> = vec![0.0; NUM_FLOATS];
-		memcpy(mapped.cast(), floats.as_mut_ptr(), floats.len());
-		
-		Ok(floats)
-	}
-
-	unsafe fn destroy(
-		&mut self,
-		command_pool: vk::CommandPool,
-		in_buffer: vk::Buffer,
-		out_buffer: vk::Buffer,
-		descriptor_pool: vk::DescriptorPool,
-		descriptor_layout: vk::DescriptorSetLayout,
-		pipeline: vk::Pipeline,
-		pipeline_layout: vk::PipelineLayout,
-	) -> Result<()> {
-		self.logical_device.destroy_command_pool(command_pool, None);
-		self.logical_device
-			.destroy_shader_module(self.compute_shader, None);
-		self.logical_device
-			.destroy_descriptor_pool(descriptor_pool, None);
-		self.logical_device
-			.destroy_descriptor_set_layout(descriptor_layout, None);
-		self.logical_device.destroy_pipeline(pipeline, None);
-		self.logical_device
-			.destroy_pipeline_layout(pipeline_layout, None);
-		self.logical_device.destroy_buffer(in_buffer, None);
-		self.logical_device.destroy_buffer(out_buffer, None);
-		self.logical_device.free_memory(self.memory, None);
-		self.logical_device.destroy_fence(self.done_fence, None);
-		self.logical_device.destroy_device(None);
-		self.instance.destroy_instance(None);
-		Ok(())
-	}
-}
-
-fn log_validation() -> () {
-	let validation_status = if VALIDATION_ENABLED {
-		"ENABLED"
-			.color(AnsiColors::BrightWhite)
-			.on_color(AnsiColors::BrightBlue)
-	} else {
-		"DISABLED"
-			.color(AnsiColors::BrightWhite)
-			.on_color(AnsiColors::BrightGreen)
-	};
-	println!("debug extensions are {}", validation_status);
+/// Runs `source` once compiled, then again after each change to it, on the same context.
+/// Failed compiles and dispatches are reported and the watch goes on, unless the
+/// device is lost.
+#[cfg(feature = "watch")]
+fn watch(
+	builder: AppBuilder,
+	compute_config: &ComputeConfig,
+	source: &Path,
+	print_checksum: bool,
+) -> Result<()> {
+	use log::error;
+	use vk_compute::watch::FileWatcher;
+	use vk_compute::{compile_glsl, ComputeError};
+
+	if !compute_config.passes.is_empty() {
+		return Err(anyhow!(
+			"--watch replaces the main shader, which passes don't use"
+		));
+	}
+	let watcher = FileWatcher::new(source)?;
+	let builder = builder.shader_bytes(compile_glsl(source)?);
+	let mut context = unsafe { ComputeContext::new(builder, compute_config)? };
+
+	let element_count = context.app.element_count;
+	let outcome =
+		generate_input(&compute_config.input_pattern, element_count).and_then(|input| loop {
+			match unsafe { context.dispatch(&input) } {
+				Ok(results) => report_results(
+					compute_config,
+					&input,
+					&results,
+					false,
+					None,
+					print_checksum,
+				)?,
+				Err(e) if ComputeError::is_device_lost(&e) => return Err(e),
+				Err(e) => error!("{:#}", e),
+			}
+
+			info!("watching {} for changes", source.display());
+			loop {
+				watcher.wait_for_change()?;
+				let replaced = compile_glsl(source)
+					.and_then(|spirv| unsafe { context.replace_shader(&spirv) });
+				match replaced {
+					Ok(()) => break,
+					Err(e) => error!("{:#}", e),
+				}
+			}
+			info!("recompiled {}", source.display());
+		});
+
+	let destroyed = unsafe { context.destroy() };
+	outcome?;
+	destroyed
 }
 
-unsafe fn has_portability_subset_extension(
-	instance: &Instance,
-	physical_device: vk::PhysicalDevice,
-) -> Result<bool> {
-	let extension_properties =
-		instance.enumerate_device_extension_properties(physical_device, None)?;
+/// Disassembles each configured pass's shader, or the builder's one without passes.
+fn print_disassembly(builder: &AppBuilder, compute_config: &ComputeConfig) -> Result<()> {
+	if compute_config.passes.is_empty() {
+		print!("{}", disassemble(builder.shader_code())?);
+		return Ok(());
+	}
+	for (i, pass) in compute_config.passes.iter().enumerate() {
+		println!("; pass {}: {}", i, pass.shader);
+		print!("{}", disassemble(&load_shader(&pass.shader)?)?);
+	}
+	Ok(())
+}
 
-	let has_portability = extension_properties
-		.iter()
-		.map(|p| &p.extension_name)
-		.map(|n| n.to_string_lossy())
-		.any(|n| VK_KHR_PORTABILITY_SUBSET_STR == n);
-	Ok(has_portability)
+/// The bundled shader's arithmetic on the host, without touching Vulkan.
+fn run_on_host(
+	builder: AppBuilder,
+	compute_config: &ComputeConfig,
+	json: Option<bool>,
+	print_checksum: bool,
+) -> Result<()> {
+	info!("running on the {} backend", Backend::Host);
+	let input = generate_input(&compute_config.input_pattern, builder.get_element_count())?;
+	let dispatched = Instant::now();
+	let results = host::run_on_host(&input, compute_config.element_type, doubled);
+	let json = json.map(|full| JsonRun {
+		full,
+		device: None,
+		backend: Backend::Host,
+		dispatch: Some(dispatched.elapsed()),
+		pass_durations: Vec::new(),
+	});
+	report_results(compute_config, &input, &results, true, json, print_checksum)
 }
 
-#[rustfmt::skip]
-fn main() -> Result<()> {
-	pretty_env_logger::init();
-	
-	let Config {device : device_config} = get_config()?;
+/// [`run`] for the bundled shader, falling back to the host if Vulkan can't be set up.
+fn run_with_fallback(
+	builder: AppBuilder,
+	compute_config: &ComputeConfig,
+	json: Option<bool>,
+	print_checksum: bool,
+) -> Result<()> {
+	let input = generate_input(&compute_config.input_pattern, builder.get_element_count())?;
+	let (results, backend) =
+		unsafe { run_compute_or_host(builder, compute_config, &input, doubled)? };
+	info!("ran on the {} backend", backend);
+	// setup is in with the dispatch here, so no time for it alone
+	let json = json.map(|full| JsonRun {
+		full,
+		device: None,
+		backend,
+		dispatch: None,
+		pass_durations: Vec::new(),
+	});
+	report_results(compute_config, &input, &results, true, json, print_checksum)
+}
 
-	let mut app = unsafe { App::create(&device_config)? };
-	println!("found compute index {} and memory index {}", 
-		(app.queue_index).green(), (app.memory_index).green());
+/// Sets up everything a dispatch needs and tears it down again, printing how long each
+/// stage took. Nothing is uploaded, submitted or read back.
+fn dry_run(builder: AppBuilder, compute_config: &ComputeConfig) -> Result<()> {
+	let start = Instant::now();
+	let context = unsafe { ComputeContext::new(builder, compute_config) }
+		.map_err(|e| e.context("setup failed"))?;
+	print_setup_timings(&context);
+	unsafe { context.destroy()? };
+	println!(
+		"{} in {:?}",
+		"setup succeeded".if_supports_color(Stream::Stdout, |t| t.green()),
+		start.elapsed()
+	);
+	Ok(())
+}
 
-	unsafe { app.populate_buffer()? };
-	let (in_buffer, out_buffer, descriptor_layout) = unsafe {
-		app.bind_buffer_layout()? };
+/// One line per setup stage and their total, for --dry-run and --timings.
+fn print_setup_timings(context: &ComputeContext) {
+	let mut total = Duration::ZERO;
+	for (stage, duration) in context.setup_timings() {
+		println!("{:<18} {:>12.3?}", stage, duration);
+		total += *duration;
+	}
+	println!("{:<18} {:>12.3?}", "total", total);
+}
 
-	let (pipeline, pipeline_layout) = unsafe {
-		app.create_pipeine_with_layout(&descriptor_layout)? };
+/// Reads `path` as SPIR-V and prints what it declares, failing on anything that would
+/// only come out at pipeline creation otherwise.
+fn validate_shader(path: &Path) -> Result<()> {
+	let bytecode =
+		std::fs::read(path).map_err(|e| anyhow!("couldn't read {}: {}", path.display(), e))?;
+	let header = parse_spirv_header(&bytecode)
+		.map_err(|e| anyhow!("{} isn't SPIR-V: {}", path.display(), e))?;
+	println!(
+		"SPIR-V {}.{}, {} words, ids below {}, generator {:#010x}",
+		header.major,
+		header.minor,
+		bytecode.len() / 4,
+		header.bound,
+		header.generator
+	);
+
+	let layout = reflect_layout(&bytecode)
+		.map_err(|e| anyhow!("{} doesn't parse: {:#}", path.display(), e))?;
+	for binding in &layout.bindings {
+		println!(
+			"  set {} binding {}: {:?} x{}",
+			binding.set, binding.binding, binding.descriptor_type, binding.count
+		);
+	}
+	if layout.push_constant_size > 0 {
+		println!("  {} bytes of push constants", layout.push_constant_size);
+	}
+	if layout.shared_memory_size > 0 {
+		println!("  {} bytes of shared memory", layout.shared_memory_size);
+	}
 
-	let (command_pool, command_buffer) = unsafe {
-		app.create_command_pool_and_buffer()? };
-	
-	let (descriptor_pool, descriptor_set) = unsafe {
-		app.create_descriptor_pool_and_set(&in_buffer, &out_buffer, 
-			&descriptor_layout)? };
-	
-	unsafe { app.record_commands_to_buffer(
-		&command_buffer,
-		&pipeline,
-		&pipeline_layout,
-		&descriptor_set
-	)?};
+	if !run_spirv_val(path)? {
+		warn!(
+			"no spirv-val to validate with, only the header and structure were checked; set {} \
+			 to its path if it's installed elsewhere",
+			SPIRV_VAL_ENV
+		);
+	}
+	println!(
+		"{}",
+		"valid".if_supports_color(Stream::Stdout, |t| t.bright_green())
+	);
+	Ok(())
+}
 
-	// stuff happens here
-	let results = unsafe {
-		app.do_the_thing(&command_buffer)?
-	};
+fn diff(expected_path: &Path, actual_path: &Path, tolerance: f32, worst: usize) -> Result<()> {
+	let expected = read_raw_floats(expected_path)?;
+	let actual = read_raw_floats(actual_path)?;
+	let comparison = compare(&expected, &actual, worst)?;
+
+	println!(
+		"{} of {} elements differ",
+		comparison.differing, comparison.len
+	);
+	if comparison.differing > 0 {
+		println!("max absolute error {:e}", comparison.max_absolute);
+		println!("max relative error {:e}", comparison.max_relative);
+		for &index in &comparison.worst {
+			println!(
+				"  [{}] {} vs {} ({:e})",
+				index,
+				expected[index],
+				actual[index],
+				(expected[index] - actual[index]).abs()
+			);
+		}
+	}
 
-	println!("first result is {}; last result is {}",
-		results[0].color(AnsiColors::BrightWhite),
-		results[NUM_FLOATS - 1].color(AnsiColors::BrightWhite));
+	if comparison.max_absolute > tolerance {
+		let message = format!("differences exceed the tolerance of {}", tolerance);
+		println!(
+			"{}",
+			message.if_supports_color(Stream::Stdout, |t| t.bright_red())
+		);
+		return Err(anyhow!(
+			"{} and {} differ",
+			expected_path.display(),
+			actual_path.display()
+		));
+	}
+	println!(
+		"{}",
+		"within tolerance".if_supports_color(Stream::Stdout, |t| t.bright_green())
+	);
+	Ok(())
+}
+
+/// Times whole dispatches, upload and readback included, over one generated input.
+fn bench(
+	builder: AppBuilder,
+	compute_config: &ComputeConfig,
+	iterations: u32,
+	timings: bool,
+) -> Result<()> {
+	if iterations == 0 {
+		return Err(anyhow!("bench needs at least one iteration"));
+	}
+
+	let mut context = unsafe { ComputeContext::new(builder, compute_config)? };
+	if timings {
+		print_setup_timings(&context);
+	}
+
+	let outcome = generate_input(&compute_config.input_pattern, context.app.element_count)
+		.and_then(|input| {
+			let mut timings = Vec::with_capacity(iterations as usize);
+			for _ in 0..iterations {
+				let start = Instant::now();
+				unsafe { context.dispatch(&input)? };
+				timings.push(start.elapsed());
+			}
+			Ok(timings)
+		});
+
+	let destroyed = unsafe { context.destroy() };
+
+	let timings = outcome?;
+	destroyed?;
+	let total = timings.iter().sum::<Duration>();
+	println!(
+		"{} dispatches: min {:?}, mean {:?}, max {:?}",
+		iterations,
+		timings.iter().min().unwrap(),
+		total / iterations,
+		timings.iter().max().unwrap()
+	);
+	Ok(())
+}
+
+/// `verify` only makes sense for the bundled doubling shader, which has a known answer.
+/// With `json` the verdict goes in a JSON object on stdout instead of a colored line.
+#[rustfmt::skip]
+fn report_results(
+	compute_config: &ComputeConfig,
+	input: &[f32],
+	results: &[f32],
+	verify: bool,
+	json: Option<JsonRun>,
+	print_checksum: bool,
+) -> Result<()> {
+	// nothing to sample when a dimension is 0
+	if let (Some(first), Some(last)) = (results.first(), results.last()) {
+		info!("first result is {}; last result is {}",
+			first.if_supports_color(Stream::Stderr, |t| t.color(AnsiColors::BrightWhite)),
+			last.if_supports_color(Stream::Stderr, |t| t.color(AnsiColors::BrightWhite)));
+	}
 	
-	let matches_index = results
+	if let Some(output_path) = &compute_config.output_path {
+		write_output(output_path, compute_config.output_format, results)?;
+		info!("wrote results to {}", output_path.display());
+	}
+
+	let all_doubled = verify.then(|| results
 		.iter()
-		.enumerate()
-		.filter(|(idx, value)| *idx == value.round() as usize)
-		.count() == NUM_FLOATS;
+		.zip(input)
+		.all(|(result, value)| *result == 2.0 * value));
+
+	if let Some(run) = json {
+		let milliseconds = |duration: &Duration| duration.as_secs_f64() * 1000.0;
+		let report = JsonResults {
+			device: run.device,
+			backend: run.backend.to_string(),
+			element_count: results.len(),
+			dispatch_ms: run.dispatch.as_ref().map(milliseconds),
+			pass_ms: run.pass_durations.iter().map(milliseconds).collect(),
+			all_values_match: all_doubled,
+			checksum: print_checksum.then(|| checksum(results)),
+			results: JsonValues::new(results, run.full),
+		};
+		println!("{}", serde_json::to_string(&report)?);
+		return Ok(());
+	}
 
-	let did_it_work_message = match matches_index {
-		true => "all values match".color(AnsiColors::BrightGreen),
-		false => "something broke".color(AnsiColors::BrightRed)
-	};
-	
-	println!("{}", did_it_work_message);
+	if print_checksum {
+		println!("checksum {}", checksum(results));
+	}
+
+	if let Some(all_doubled) = all_doubled {
+		let (did_it_work_message, color) = match all_doubled {
+			true => ("all values match", AnsiColors::BrightGreen),
+			false => ("something broke", AnsiColors::BrightRed)
+		};
 
-	unsafe { 
-		app.destroy(
-			command_pool,
-			in_buffer, out_buffer,
-			descriptor_pool, descriptor_layout,
-			pipeline, pipeline_layout
-		)
+		println!("{}", did_it_work_message.if_supports_color(Stream::Stdout, |t| t.color(color)));
 	}
+
+	Ok(())
 }
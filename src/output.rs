@@ -0,0 +1,46 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+	/// little-endian `f32`s, readable back with `input_pattern = { from_file = ... }`
+	#[default]
+	Raw,
+	/// one value per line
+	Text,
+	/// an `index,value` header followed by one row per element
+	Csv,
+}
+
+pub fn write_output(path: &Path, format: OutputFormat, values: &[f32]) -> Result<()> {
+	let file = File::create(path)
+		.map_err(|e| anyhow!("couldn't create output file {}: {}", path.display(), e))?;
+	let mut writer = BufWriter::new(file);
+
+	match format {
+		OutputFormat::Raw => {
+			for value in values {
+				writer.write_all(&value.to_le_bytes())?;
+			}
+		}
+		OutputFormat::Text => {
+			for value in values {
+				writeln!(writer, "{}", value)?;
+			}
+		}
+		OutputFormat::Csv => {
+			writeln!(writer, "index,value")?;
+			for (index, value) in values.iter().enumerate() {
+				writeln!(writer, "{},{}", index, value)?;
+			}
+		}
+	}
+
+	writer.flush()?;
+	Ok(())
+}
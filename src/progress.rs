@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Element counts below this are generated and copied too quickly to be worth a bar.
+pub const PROGRESS_THRESHOLD: usize = 1 << 24;
+
+/// How many elements go by between updates, so the bar costs next to nothing per element.
+#[cfg(feature = "progress")]
+const STEP: usize = 1 << 16;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turns progress bars off altogether, e.g. for `--no-color` or when piping output.
+pub fn set_enabled(enabled: bool) {
+	ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// A bar on stderr for a long host-side loop, or nothing without the `progress` feature,
+/// below [`PROGRESS_THRESHOLD`], when disabled, or when stdout isn't a terminal.
+pub struct Progress {
+	#[cfg(feature = "progress")]
+	bar: Option<indicatif::ProgressBar>,
+}
+
+impl Progress {
+	#[cfg(feature = "progress")]
+	pub fn new(message: &'static str, total: usize) -> Self {
+		use std::io::IsTerminal;
+
+		let shown = total >= PROGRESS_THRESHOLD
+			&& ENABLED.load(Ordering::Relaxed)
+			&& std::io::stdout().is_terminal();
+		let bar = shown.then(|| {
+			let bar = indicatif::ProgressBar::new(total as u64).with_message(message);
+			bar.set_style(
+				indicatif::ProgressStyle::with_template("{msg} {wide_bar} {pos}/{len}")
+					.expect("the template is valid"),
+			);
+			bar
+		});
+		Self { bar }
+	}
+
+	#[cfg(not(feature = "progress"))]
+	pub fn new(_message: &'static str, _total: usize) -> Self {
+		Self {}
+	}
+
+	/// Moves the bar to `position`, though only every so many elements.
+	pub fn at(&self, position: usize) {
+		#[cfg(feature = "progress")]
+		if let Some(bar) = &self.bar {
+			if position.is_multiple_of(STEP) {
+				bar.set_position(position as u64);
+			}
+		}
+		#[cfg(not(feature = "progress"))]
+		let _ = position;
+	}
+
+	pub fn finish(self) {
+		#[cfg(feature = "progress")]
+		if let Some(bar) = self.bar {
+			bar.finish_and_clear();
+		}
+	}
+}
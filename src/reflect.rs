@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use rspirv_reflect::{BindingCount, Reflection};
+use vulkanalia::prelude::v1_1::*;
+
+use crate::app::{config_descriptor_bindings, AppBuilder, DescriptorBinding, ExtraBuffer};
+use crate::{load_shader, ComputeConfig};
+
+/// What a pipeline layout needs to hold for a shader, read out of its SPIR-V.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderLayout {
+	pub bindings: Vec<DescriptorBinding>,
+	/// bytes of push constants the shader declares, 0 for none
+	pub push_constant_size: u32,
+	/// bytes of `shared` memory a workgroup needs, see [`shared_memory_size`]
+	pub shared_memory_size: u64,
+}
+
+/// Bindings in every descriptor set the shader uses, each with its set.
+pub fn reflect_layout(spirv: &[u8]) -> Result<ShaderLayout> {
+	check_instruction_lengths(spirv)?;
+	let reflection = Reflection::new_from_spirv(spirv)?;
+	let storage_bindings = storage_class_bindings(spirv);
+
+	let mut bindings = Vec::new();
+	for (set, descriptors) in reflection.get_descriptor_sets()? {
+		for (binding, info) in descriptors {
+			let count = match info.binding_count {
+				BindingCount::One => 1,
+				BindingCount::StaticSized(count) => count as u32,
+				BindingCount::Unbounded => {
+					return Err(anyhow!(
+						"binding {} of set {} is an unbounded array",
+						binding,
+						set
+					));
+				}
+			};
+			let descriptor_type = if storage_bindings.contains(&(set, binding)) {
+				vk::DescriptorType::STORAGE_BUFFER
+			} else {
+				vk::DescriptorType::from_raw(info.ty.0 as i32)
+			};
+			bindings.push(DescriptorBinding {
+				set,
+				binding,
+				descriptor_type,
+				count,
+			});
+		}
+	}
+
+	let push_constant_size = reflection
+		.get_push_constant_range()?
+		.map_or(0, |range| range.offset + range.size);
+
+	Ok(ShaderLayout {
+		bindings,
+		push_constant_size,
+		shared_memory_size: shared_memory_size(spirv),
+	})
+}
+
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const STORAGE_CLASS_WORKGROUP: u32 = 4;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+/// Each instruction's opcode and operands, skipping the five-word header.
+fn instructions(spirv: &[u8]) -> Vec<(u32, Vec<u32>)> {
+	let words = spirv
+		.chunks_exact(4)
+		.map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+		.collect::<Vec<_>>();
+
+	let mut instructions = Vec::new();
+	let mut i = 5;
+	while i < words.len() {
+		let word_count = (words[i] >> 16) as usize;
+		let operands = words[i + 1..(i + word_count).min(words.len())].to_vec();
+		instructions.push((words[i] & 0xffff, operands));
+		i += word_count.max(1);
+	}
+	instructions
+}
+
+/// Checks every instruction ends within the module, which rspirv takes on trust and
+/// panics over otherwise, e.g. for a truncated file.
+fn check_instruction_lengths(spirv: &[u8]) -> Result<()> {
+	let word_total = spirv.len() / 4;
+	let mut i = 5;
+	while i < word_total {
+		let offset = i * 4;
+		let word = u32::from_ne_bytes([
+			spirv[offset],
+			spirv[offset + 1],
+			spirv[offset + 2],
+			spirv[offset + 3],
+		]);
+		let word_count = (word >> 16) as usize;
+		if word_count == 0 {
+			return Err(anyhow!("instruction at word {} has a word count of 0", i));
+		}
+		if i + word_count > word_total {
+			return Err(anyhow!(
+				"instruction at word {} runs {} words past the end of the module",
+				i,
+				i + word_count - word_total
+			));
+		}
+		i += word_count;
+	}
+	Ok(())
+}
+
+/// Bytes of `shared` variables, added up without any padding, so the real footprint
+/// may be a little more. Types it doesn't know count as nothing.
+pub fn shared_memory_size(spirv: &[u8]) -> u64 {
+	let instructions = instructions(spirv);
+
+	// types and constants by result id; constants have theirs after the result type
+	let mut definitions = HashMap::new();
+	for (opcode, operands) in &instructions {
+		let result = match *opcode {
+			OP_CONSTANT => operands.get(1),
+			OP_TYPE_BOOL..=OP_TYPE_POINTER => operands.first(),
+			_ => None,
+		};
+		if let Some(result) = result {
+			definitions.insert(*result, (*opcode, operands.as_slice()));
+		}
+	}
+
+	instructions
+		.iter()
+		.filter(|(opcode, operands)| {
+			*opcode == OP_VARIABLE && operands.get(2) == Some(&STORAGE_CLASS_WORKGROUP)
+		})
+		.map(|(_, operands)| type_size(&definitions, operands[0]))
+		.sum()
+}
+
+fn type_size(definitions: &HashMap<u32, (u32, &[u32])>, id: u32) -> u64 {
+	let Some((opcode, operands)) = definitions.get(&id) else {
+		return 0;
+	};
+	match *opcode {
+		OP_TYPE_BOOL => 4,
+		OP_TYPE_INT | OP_TYPE_FLOAT => operands[1] as u64 / 8,
+		OP_TYPE_VECTOR | OP_TYPE_MATRIX => type_size(definitions, operands[1]) * operands[2] as u64,
+		OP_TYPE_ARRAY => {
+			let length = match definitions.get(&operands[2]) {
+				Some((OP_CONSTANT, constant)) => constant[2] as u64,
+				_ => 0,
+			};
+			type_size(definitions, operands[1]) * length
+		}
+		OP_TYPE_STRUCT => operands[1..]
+			.iter()
+			.map(|member| type_size(definitions, *member))
+			.sum(),
+		OP_TYPE_POINTER => type_size(definitions, operands[2]),
+		_ => 0,
+	}
+}
+
+/// Sets and bindings of variables in the `StorageBuffer` storage class. rspirv-reflect
+/// only looks at that class from SPIR-V 1.3 on, so older modules using it through
+/// `SPV_KHR_storage_buffer_storage_class` (glslang's default) come out as uniform buffers.
+fn storage_class_bindings(spirv: &[u8]) -> Vec<(u32, u32)> {
+	let mut storage_variables = Vec::new();
+	let mut binding_decorations = Vec::new();
+	let mut set_decorations = HashMap::new();
+	for (opcode, operands) in instructions(spirv) {
+		match opcode {
+			OP_VARIABLE if operands.get(2) == Some(&STORAGE_CLASS_STORAGE_BUFFER) => {
+				storage_variables.push(operands[1]);
+			}
+			OP_DECORATE if operands.get(1) == Some(&DECORATION_BINDING) => {
+				if let Some(binding) = operands.get(2) {
+					binding_decorations.push((operands[0], *binding));
+				}
+			}
+			OP_DECORATE if operands.get(1) == Some(&DECORATION_DESCRIPTOR_SET) => {
+				if let Some(set) = operands.get(2) {
+					set_decorations.insert(operands[0], *set);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	// undecorated variables are in set 0, as rspirv-reflect has them
+	binding_decorations
+		.into_iter()
+		.filter(|(id, _)| storage_variables.contains(id))
+		.map(|(id, binding)| (set_decorations.get(&id).copied().unwrap_or(0), binding))
+		.collect()
+}
+
+/// One layout covering every shader, for passes that share a pipeline layout. The same
+/// binding of the same set has to mean the same thing in all of them.
+pub fn merge_layouts(layouts: impl IntoIterator<Item = ShaderLayout>) -> Result<ShaderLayout> {
+	let mut merged = ShaderLayout::default();
+	for layout in layouts {
+		for binding in layout.bindings {
+			match merged
+				.bindings
+				.iter()
+				.find(|b| (b.set, b.binding) == (binding.set, binding.binding))
+			{
+				Some(existing) if *existing != binding => {
+					return Err(anyhow!(
+						"shaders disagree about binding {} of set {}: {:?} x{} and {:?} x{}",
+						binding.binding,
+						binding.set,
+						existing.descriptor_type,
+						existing.count,
+						binding.descriptor_type,
+						binding.count
+					));
+				}
+				Some(_) => {}
+				None => merged.bindings.push(binding),
+			}
+		}
+		merged.push_constant_size = merged.push_constant_size.max(layout.push_constant_size);
+		merged.shared_memory_size = merged.shared_memory_size.max(layout.shared_memory_size);
+	}
+	merged.bindings.sort_by_key(|b| (b.set, b.binding));
+	Ok(merged)
+}
+
+/// The layout shared by every shader that will run: the configured passes, or the
+/// builder's shader without any. The input and output buffers are always in it, since
+/// every dispatch binds them.
+pub fn reflect_shaders(builder: &AppBuilder, config: &ComputeConfig) -> Result<ShaderLayout> {
+	let mut layouts = vec![ShaderLayout {
+		bindings: config_descriptor_bindings(&[], config.descriptor_set),
+		..ShaderLayout::default()
+	}];
+	if config.passes.is_empty() {
+		layouts.push(reflect_layout(builder.shader_code())?);
+	}
+	for pass in &config.passes {
+		layouts.push(reflect_layout(&load_shader(&pass.shader)?)?);
+	}
+	merge_layouts(layouts)
+}
+
+/// Checks `bindings` against the buffers there are to put in them: the input and output
+/// buffers at 0 and 1 of `descriptor_set` and `extra_buffers` after. Returns the extra
+/// buffers the layout has a place for; configured ones the shaders never use are left
+/// out with a warning.
+pub fn bound_extra_buffers(
+	bindings: &[DescriptorBinding],
+	extra_buffers: &[ExtraBuffer],
+	descriptor_set: u32,
+) -> Result<Vec<ExtraBuffer>> {
+	for binding in bindings {
+		let expected = match (binding.set, binding.binding) {
+			(set, 0 | 1) if set == descriptor_set => Some(vk::DescriptorType::STORAGE_BUFFER),
+			key => extra_buffers
+				.iter()
+				.find(|extra| (extra.set, extra.binding) == key)
+				.map(|extra| extra.kind.descriptor_type()),
+		};
+		match expected {
+			None => {
+				return Err(anyhow!(
+					"shader uses binding {} of set {} but there's no [[compute.bindings]] entry for it",
+					binding.binding,
+					binding.set
+				));
+			}
+			Some(expected) if expected != binding.descriptor_type || binding.count != 1 => {
+				return Err(anyhow!(
+					"shader declares binding {} of set {} as {:?} x{}, but it's bound as one {:?}",
+					binding.binding,
+					binding.set,
+					binding.descriptor_type,
+					binding.count,
+					expected
+				));
+			}
+			Some(_) => {}
+		}
+	}
+
+	Ok(extra_buffers
+		.iter()
+		.filter(|extra| {
+			let used = bindings
+				.iter()
+				.any(|b| (b.set, b.binding) == (extra.set, extra.binding));
+			if !used {
+				warn!(
+					"no shader uses binding {} of set {}, leaving it out",
+					extra.binding, extra.set
+				);
+			}
+			used
+		})
+		.copied()
+		.collect())
+}
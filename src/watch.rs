@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How long a file has to go quiet before a change counts, since editors often save in
+/// several writes.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches one file through its directory, so editors that save by replacing the file
+/// rather than writing to it are still seen.
+pub struct FileWatcher {
+	path: PathBuf,
+	events: Receiver<notify::Result<Event>>,
+	// events stop once it's dropped
+	_watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+	pub fn new(path: &Path) -> Result<Self> {
+		let path = path
+			.canonicalize()
+			.map_err(|e| anyhow!("couldn't watch {}: {}", path.display(), e))?;
+		let directory = path
+			.parent()
+			.ok_or_else(|| anyhow!("couldn't watch {}, it has no directory", path.display()))?;
+
+		let (sender, events) = channel();
+		let mut watcher = notify::recommended_watcher(sender)?;
+		watcher.watch(directory, RecursiveMode::NonRecursive)?;
+		Ok(Self {
+			path,
+			events,
+			_watcher: watcher,
+		})
+	}
+
+	/// Blocks until the file has been written or replaced and then left alone for
+	/// [`DEBOUNCE`].
+	pub fn wait_for_change(&self) -> Result<()> {
+		loop {
+			let event = self.events.recv()?;
+			if self.is_change(&event?) {
+				break;
+			}
+		}
+		loop {
+			match self.events.recv_timeout(DEBOUNCE) {
+				Ok(_) => {}
+				Err(RecvTimeoutError::Timeout) => return Ok(()),
+				Err(RecvTimeoutError::Disconnected) => {
+					return Err(anyhow!("stopped watching {}", self.path.display()));
+				}
+			}
+		}
+	}
+
+	fn is_change(&self, event: &Event) -> bool {
+		matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+			&& event.paths.contains(&self.path)
+	}
+}
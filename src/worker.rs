@@ -0,0 +1,81 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Result};
+
+use crate::app::AppBuilder;
+use crate::context::ComputeContext;
+use crate::{ComputeConfig, ComputeError};
+
+/// Whatever the caller uses to match results up with inputs.
+pub type JobId = u64;
+
+/// Handles to a [`ComputeContext`] on a thread of its own, from [`spawn_compute`]. The
+/// context is created, used and destroyed on that one thread, so the device's handles
+/// never cross threads and its `unsafe` calls can't race; callers only see channels.
+pub struct ComputeWorker {
+	/// inputs to dispatch, one at a time in the order sent
+	pub jobs: Sender<(JobId, Vec<f32>)>,
+	/// each job's output or error, in the order the jobs were sent
+	pub results: Receiver<(JobId, Result<Vec<f32>>)>,
+	thread: JoinHandle<Result<()>>,
+}
+
+impl ComputeWorker {
+	/// Stops taking jobs, lets the ones already sent finish and tears the context
+	/// down, handing back any error from that. Results not yet received are dropped.
+	pub fn finish(self) -> Result<()> {
+		drop(self.jobs);
+		self.thread
+			.join()
+			.map_err(|_| anyhow!("the compute worker panicked"))?
+	}
+}
+
+/// Sets up a context on a new thread and dispatches every job sent to it there. Returns
+/// once setup is done, with its error if it failed. The worker stops when
+/// [`ComputeWorker::finish`] is called, the results are no longer received, or the
+/// device is lost, after reporting that job's error.
+pub fn spawn_compute(builder: AppBuilder, config: &ComputeConfig) -> Result<ComputeWorker> {
+	let (jobs, job_receiver) = channel::<(JobId, Vec<f32>)>();
+	let (result_sender, results) = channel();
+	let (setup_sender, setup) = channel();
+	let config = config.clone();
+
+	let thread = thread::Builder::new()
+		.name("vk_compute".to_string())
+		.spawn(move || {
+			let mut context = match unsafe { ComputeContext::new(builder, &config) } {
+				Ok(context) => {
+					let _ = setup_sender.send(Ok(()));
+					context
+				}
+				Err(e) => {
+					let _ = setup_sender.send(Err(e));
+					return Ok(());
+				}
+			};
+
+			for (id, input) in job_receiver {
+				let result = unsafe { context.dispatch(&input) };
+				let lost = result.as_ref().is_err_and(ComputeError::is_device_lost);
+				if result_sender.send((id, result)).is_err() || lost {
+					break;
+				}
+			}
+			unsafe { context.destroy() }
+		})?;
+
+	match setup.recv() {
+		Ok(Ok(())) => Ok(ComputeWorker {
+			jobs,
+			results,
+			thread,
+		}),
+		Ok(Err(e)) => {
+			let _ = thread.join();
+			Err(e)
+		}
+		Err(_) => Err(anyhow!("the compute worker panicked during setup")),
+	}
+}
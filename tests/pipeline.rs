@@ -0,0 +1,78 @@
+use vk_compute::app::{create_entry, create_instance, AppBuilder, DEFAULT_ELEMENT_COUNT};
+use vk_compute::context::run_compute;
+use vk_compute::input::{generate_input, InputPattern};
+use vk_compute::worker::spawn_compute;
+use vk_compute::{has_compute_queue, ApplicationConfig, ComputeConfig};
+use vulkanalia::prelude::v1_1::*;
+
+/// Whether there's a loader and at least one device that can run compute.
+fn has_compute_device() -> bool {
+	unsafe {
+		let Ok(entry) = create_entry() else {
+			return false;
+		};
+		let Ok(instance) = create_instance(&entry, &ApplicationConfig::default(), false, &[], &[])
+		else {
+			return false;
+		};
+		let found = instance
+			.enumerate_physical_devices()
+			.unwrap_or_default()
+			.into_iter()
+			.any(|physical_device| has_compute_queue(&instance, physical_device));
+		instance.destroy_instance(None);
+		found
+	}
+}
+
+#[test]
+fn bundled_shader_doubles_a_ramp() {
+	if !has_compute_device() {
+		eprintln!("skipping: no Vulkan loader or compute-capable device");
+		return;
+	}
+
+	let input = generate_input(&InputPattern::Ramp, DEFAULT_ELEMENT_COUNT).unwrap();
+	let results = unsafe {
+		run_compute(
+			AppBuilder::new().validation(false),
+			&ComputeConfig::default(),
+			&input,
+		)
+	}
+	.unwrap();
+
+	assert_eq!(results.len(), input.len());
+	for (i, (result, value)) in results.iter().zip(&input).enumerate() {
+		assert_eq!(*result, 2.0 * value, "element {}", i);
+	}
+}
+
+#[test]
+fn worker_returns_results_in_job_order() {
+	if !has_compute_device() {
+		eprintln!("skipping: no Vulkan loader or compute-capable device");
+		return;
+	}
+
+	let worker = spawn_compute(
+		AppBuilder::new().validation(false),
+		&ComputeConfig::default(),
+	)
+	.unwrap();
+	let input = generate_input(&InputPattern::Ramp, DEFAULT_ELEMENT_COUNT).unwrap();
+	for id in 0..3 {
+		let scaled = input.iter().map(|value| value * id as f32).collect();
+		worker.jobs.send((id, scaled)).unwrap();
+	}
+
+	for id in 0..3 {
+		let (result_id, results) = worker.results.recv().unwrap();
+		assert_eq!(result_id, id);
+		let results = results.unwrap();
+		for (i, (result, value)) in results.iter().zip(&input).enumerate() {
+			assert_eq!(*result, 2.0 * value * id as f32, "job {} element {}", id, i);
+		}
+	}
+	worker.finish().unwrap();
+}